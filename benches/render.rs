@@ -0,0 +1,60 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rustracer::{render_hdr_with, scene_file::load_scene, tiles::TileOrder, RenderOptions, Scene};
+
+// loading reads and parses files, so the benchmarks call this outside the timed part
+fn scene() -> Scene<f64> {
+    let mut scene = load_scene("scenes/spheres.toml").unwrap().scene;
+    scene.width = 96;
+    scene.height = 64;
+    scene.samples = 2;
+    scene
+}
+
+// a tile size of one hands every pixel to the pool as its own job, which shows what scheduling
+// that many jobs costs
+fn tile_sizes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tile_size");
+    group.sample_size(10);
+    for &tile_size in &[1, 4, 16, 64] {
+        let options = RenderOptions {
+            tile_size,
+            ..RenderOptions::default()
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(tile_size), &options, |b, o| {
+            b.iter_batched(
+                scene,
+                |s| render_hdr_with(s, o).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn thread_scaling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("threads");
+    group.sample_size(10);
+    let mut threads = 1;
+    while threads <= num_cpus::get() {
+        for &(name, tile_size) in &[("per_pixel", 1), ("tiled", 16)] {
+            let options = RenderOptions {
+                threads,
+                tile_size,
+                tile_order: TileOrder::Hilbert,
+                ..RenderOptions::default()
+            };
+            group.bench_with_input(BenchmarkId::new(name, threads), &options, |b, o| {
+                b.iter_batched(
+                    scene,
+                    |s| render_hdr_with(s, o).unwrap(),
+                    BatchSize::PerIteration,
+                )
+            });
+        }
+        threads *= 2;
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tile_sizes, thread_scaling);
+criterion_main!(benches);
@@ -0,0 +1,428 @@
+use nalgebra as na;
+use num::ToPrimitive;
+
+use crate::ray::Ray;
+
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// cost of visiting a node relative to intersecting a single primitive
+const TRAVERSAL_COST: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub min: na::Point3<T>,
+    pub max: na::Point3<T>,
+}
+
+impl<T> Aabb<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub fn new(min: na::Point3<T>, max: na::Point3<T>) -> Aabb<T> {
+        Aabb { min, max }
+    }
+
+    pub fn empty() -> Aabb<T> {
+        let max = T::max_value();
+        Aabb {
+            min: na::Point3::new(max, max, max),
+            max: na::Point3::new(-max, -max, -max),
+        }
+    }
+
+    pub fn from_points<'a, I>(points: I) -> Aabb<T>
+    where
+        I: IntoIterator<Item = &'a na::Point3<T>>,
+    {
+        points
+            .into_iter()
+            .fold(Aabb::empty(), |bounds, point| bounds.grow(point))
+    }
+
+    pub fn grow(&self, point: &na::Point3<T>) -> Aabb<T> {
+        Aabb {
+            min: self.min.coords.inf(&point.coords).into(),
+            max: self.max.coords.sup(&point.coords).into(),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb<T>) -> Aabb<T> {
+        Aabb {
+            min: self.min.coords.inf(&other.min.coords).into(),
+            max: self.max.coords.sup(&other.max.coords).into(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|axis| self.min[axis] > self.max[axis])
+    }
+
+    pub fn center(&self) -> na::Point3<T> {
+        na::center(&self.min, &self.max)
+    }
+
+    pub fn surface_area(&self) -> T {
+        if self.is_empty() {
+            return T::zero();
+        }
+
+        let extent = self.max - self.min;
+        (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x) * na::convert(2.0)
+    }
+
+    pub fn contains(&self, point: &na::Point3<T>, tolerance: T) -> bool {
+        (0..3).all(|axis| {
+            point[axis] >= self.min[axis] - tolerance && point[axis] <= self.max[axis] + tolerance
+        })
+    }
+
+    // distance along the ray at which it enters the box, if it does at all
+    pub fn intersect(&self, ray: &Ray<T>, inv_direction: &na::Vector3<T>) -> Option<T> {
+        let mut t_min = T::zero();
+        let mut t_max = T::max_value();
+        for axis in 0..3 {
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_direction[axis];
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_direction[axis];
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
+
+#[derive(Debug)]
+enum Node<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    Leaf {
+        bounds: Aabb<T>,
+        start: usize,
+        end: usize,
+    },
+    // the left child always directly follows its parent
+    Branch {
+        bounds: Aabb<T>,
+        right: usize,
+        axis: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Primitive<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    index: usize,
+    bounds: Aabb<T>,
+    centroid: na::Point3<T>,
+}
+
+#[derive(Debug)]
+pub struct Bvh<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    nodes: Vec<Node<T>>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl<T> Bvh<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    // items without bounds are kept out of the tree and always tested
+    pub fn build(bounds: &[Option<Aabb<T>>]) -> Bvh<T> {
+        let mut primitives = Vec::with_capacity(bounds.len());
+        let mut unbounded = Vec::new();
+        for (index, bounds) in bounds.iter().enumerate() {
+            match bounds {
+                Some(bounds) => primitives.push(Primitive {
+                    index,
+                    bounds: *bounds,
+                    centroid: bounds.center(),
+                }),
+                None => unbounded.push(index),
+            }
+        }
+
+        let mut nodes = Vec::new();
+        if !primitives.is_empty() {
+            build_node(&mut nodes, &mut primitives, 0);
+        }
+
+        Bvh {
+            nodes,
+            indices: primitives.iter().map(|p| p.index).collect(),
+            unbounded,
+        }
+    }
+
+    // returns the index and distance of the closest item hit by the ray
+    pub fn intersect<F>(&self, ray: &Ray<T>, mut intersect: F) -> Option<(usize, T)>
+    where
+        F: FnMut(usize) -> Option<T>,
+    {
+        let mut closest: Option<(usize, T)> = None;
+        let mut test = |index: usize, closest: &mut Option<(usize, T)>| {
+            if let Some(distance) = intersect(index) {
+                if closest.is_none_or(|(_, d)| distance < d) {
+                    *closest = Some((index, distance));
+                }
+            }
+        };
+
+        for &index in &self.unbounded {
+            test(index, &mut closest);
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let inv_direction = ray.direction.map(|d| T::one() / d);
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let bounds = match node {
+                Node::Leaf { bounds, .. } | Node::Branch { bounds, .. } => bounds,
+            };
+            match bounds.intersect(ray, &inv_direction) {
+                Some(entry) if closest.is_none_or(|(_, d)| entry <= d) => {}
+                _ => continue,
+            }
+
+            match *node {
+                Node::Leaf { start, end, .. } => {
+                    for &index in &self.indices[start..end] {
+                        test(index, &mut closest);
+                    }
+                }
+                Node::Branch { right, axis, .. } => {
+                    let left = index + 1;
+                    if ray.direction[axis] < T::zero() {
+                        stack.push(left);
+                        stack.push(right);
+                    } else {
+                        stack.push(right);
+                        stack.push(left);
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+
+    // finds the item whose bounds contain the point with the lowest score
+    pub fn locate<F>(&self, point: &na::Point3<T>, tolerance: T, mut score: F) -> Option<usize>
+    where
+        F: FnMut(usize) -> Option<T>,
+    {
+        let mut best: Option<(usize, T)> = None;
+        let mut test = |index: usize, best: &mut Option<(usize, T)>| {
+            if let Some(s) = score(index) {
+                if best.is_none_or(|(_, b)| s < b) {
+                    *best = Some((index, s));
+                }
+            }
+        };
+
+        for &index in &self.unbounded {
+            test(index, &mut best);
+        }
+
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(node) = stack.pop() {
+            match self.nodes[node] {
+                Node::Leaf { bounds, start, end } => {
+                    if bounds.contains(point, tolerance) {
+                        for &index in &self.indices[start..end] {
+                            test(index, &mut best);
+                        }
+                    }
+                }
+                Node::Branch { bounds, right, .. } => {
+                    if bounds.contains(point, tolerance) {
+                        stack.push(node + 1);
+                        stack.push(right);
+                    }
+                }
+            }
+        }
+
+        best.map(|(index, _)| index)
+    }
+}
+
+fn build_node<T>(nodes: &mut Vec<Node<T>>, primitives: &mut [Primitive<T>], start: usize) -> usize
+where
+    T: na::RealField + ToPrimitive,
+{
+    let index = nodes.len();
+    let bounds = primitives
+        .iter()
+        .fold(Aabb::empty(), |bounds, p| bounds.union(&p.bounds));
+
+    let (axis, mid) = match split(primitives, &bounds) {
+        Some(split) => split,
+        None => {
+            nodes.push(Node::Leaf {
+                bounds,
+                start,
+                end: start + primitives.len(),
+            });
+            return index;
+        }
+    };
+
+    nodes.push(Node::Branch {
+        bounds,
+        right: 0,
+        axis,
+    });
+    let (left, right) = primitives.split_at_mut(mid);
+    build_node(nodes, left, start);
+    let right_index = build_node(nodes, right, start + mid);
+    if let Node::Branch { right, .. } = &mut nodes[index] {
+        *right = right_index;
+    }
+    index
+}
+
+// binned surface area heuristic, partitions the primitives and returns the axis and split point
+fn split<T>(primitives: &mut [Primitive<T>], bounds: &Aabb<T>) -> Option<(usize, usize)>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let count = primitives.len();
+    if count <= 1 {
+        return None;
+    }
+
+    let centroid_bounds = Aabb::from_points(primitives.iter().map(|p| &p.centroid));
+    let bins_t = T::from_usize(BINS).unwrap();
+    let bin_of = |p: &Primitive<T>, axis: usize| {
+        let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+        let offset = (p.centroid[axis] - centroid_bounds.min[axis]) / extent;
+        (offset * bins_t).to_usize().unwrap_or(0).min(BINS - 1)
+    };
+
+    let parent_area = bounds.surface_area();
+    let mut best: Option<(T, usize, usize)> = None;
+    for axis in 0..3 {
+        if centroid_bounds.max[axis] <= centroid_bounds.min[axis] {
+            continue;
+        }
+
+        let mut bins = [(Aabb::empty(), 0); BINS];
+        for p in primitives.iter() {
+            let bin = &mut bins[bin_of(p, axis)];
+            bin.0 = bin.0.union(&p.bounds);
+            bin.1 += 1;
+        }
+
+        for split in 1..BINS {
+            let side = |bins: &[(Aabb<T>, usize)]| {
+                bins.iter()
+                    .fold((Aabb::empty(), 0), |(b, n), (bin, c)| (b.union(bin), n + c))
+            };
+            let (left, left_count) = side(&bins[..split]);
+            let (right, right_count) = side(&bins[split..]);
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+
+            let cost = na::convert::<f64, T>(TRAVERSAL_COST)
+                + (left.surface_area() * T::from_usize(left_count).unwrap()
+                    + right.surface_area() * T::from_usize(right_count).unwrap())
+                    / parent_area;
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    let (cost, axis, split) = best?;
+    if count <= MAX_LEAF_SIZE && cost >= T::from_usize(count).unwrap() {
+        return None;
+    }
+
+    let mut mid = 0;
+    for i in 0..count {
+        if bin_of(&primitives[i], axis) < split {
+            primitives.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((axis, mid))
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+
+    use super::*;
+
+    fn sphere_hit(center: &na::Point3<f64>, ray: &Ray<f64>) -> Option<f64> {
+        let l = center - ray.origin;
+        let adj = l.dot(&ray.direction);
+        let d2 = l.dot(&l) - adj * adj;
+        if d2 > 0.25 || adj < 0.0 {
+            return None;
+        }
+        Some(adj - (0.25 - d2).sqrt())
+    }
+
+    #[test]
+    fn matches_linear_scan() {
+        let centers = (0..1000)
+            .map(|i| {
+                let i = f64::from(i);
+                na::Point3::new(
+                    (i * 7.3) % 20.0 - 10.0,
+                    (i * 3.1) % 20.0 - 10.0,
+                    -(i % 30.0),
+                )
+            })
+            .collect::<Vec<_>>();
+        let bounds = centers
+            .iter()
+            .map(|c| {
+                let r = na::Vector3::new(0.5, 0.5, 0.5);
+                Some(Aabb::new(c - r, c + r))
+            })
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(&bounds);
+
+        for i in 0..500 {
+            let i = f64::from(i);
+            let ray = Ray {
+                origin: na::Point3::new(0.0, 0.0, 5.0),
+                direction: na::Vector3::new((i * 0.37).sin(), (i * 0.73).cos(), -1.0).normalize(),
+            };
+
+            let linear = centers
+                .iter()
+                .enumerate()
+                .filter_map(|(j, c)| sphere_hit(c, &ray).map(|d| (j, d)))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            let accelerated = bvh.intersect(&ray, |j| sphere_hit(&centers[j], &ray));
+            assert_eq!(linear.map(|(_, d)| d), accelerated.map(|(_, d)| d));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use nalgebra as na;
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::sampling::concentric_disk;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FovAxis {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub eye: na::Point3<T>,
+    pub target: na::Point3<T>,
+    pub up: na::Vector3<T>,
+    // in degrees, measured along fov_axis
+    pub fov: T,
+    pub fov_axis: FovAxis,
+    // radius of the lens, zero for a pinhole camera
+    pub aperture: T,
+    // distance along the view direction that is in perfect focus
+    pub focus_distance: T,
+}
+
+impl<T> Camera<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    // right, up and backward axes of the camera in world space
+    pub fn basis(&self) -> (na::Vector3<T>, na::Vector3<T>, na::Vector3<T>) {
+        let backward = (self.eye - self.target).normalize();
+        // an up parallel to the view direction doesn't say which way is up, so the world axis
+        // furthest from the view direction stands in for it
+        let right = self
+            .up
+            .cross(&backward)
+            .try_normalize(na::convert(1e-12))
+            .unwrap_or_else(|| {
+                let mut up = na::Vector3::zeros();
+                up[backward.iamin()] = T::one();
+                up.cross(&backward).normalize()
+            });
+        let up = backward.cross(&right);
+        (right, up, backward)
+    }
+
+    // half the width and height of the image plane at unit distance from the eye
+    pub fn sensor_size(&self, aspect: T) -> (T, T) {
+        let half_fov = (self.fov * T::pi() / na::convert(180.0)) / na::convert(2.0);
+        let extent = half_fov.tan();
+        match self.fov_axis {
+            FovAxis::Horizontal => (extent, extent / aspect),
+            FovAxis::Vertical => (extent * aspect, extent),
+        }
+    }
+
+    // point on the lens for a sample in the unit square
+    pub fn lens_point(&self, sample: &na::Vector2<T>) -> na::Point3<T> {
+        if self.aperture <= T::zero() {
+            return self.eye;
+        }
+
+        let (right, up, _) = self.basis();
+        let offset = concentric_disk(sample) * self.aperture;
+        self.eye + right * offset.x + up * offset.y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+
+    use super::*;
+
+    fn camera(target: na::Point3<f64>, up: na::Vector3<f64>) -> Camera<f64> {
+        Camera {
+            eye: na::Point3::new(1.0, 2.0, 3.0),
+            target,
+            up,
+            fov: 60.0,
+            fov_axis: FovAxis::Vertical,
+            aperture: 0.0,
+            focus_distance: 1.0,
+        }
+    }
+
+    fn assert_orthonormal(basis: (na::Vector3<f64>, na::Vector3<f64>, na::Vector3<f64>)) {
+        let (right, up, backward) = basis;
+        for axis in &[right, up, backward] {
+            assert!((axis.norm() - 1.0).abs() < 1e-12, "{:?}", basis);
+        }
+        assert!(right.dot(&up).abs() < 1e-12, "{:?}", basis);
+        assert!(right.dot(&backward).abs() < 1e-12, "{:?}", basis);
+        assert!(up.dot(&backward).abs() < 1e-12, "{:?}", basis);
+        // right handed, so right x up points back towards the eye
+        assert!((right.cross(&up) - backward).norm() < 1e-12, "{:?}", basis);
+    }
+
+    #[test]
+    fn basis_looks_at_target_with_up_on_top() {
+        let camera = camera(na::Point3::new(4.0, 2.0, -1.0), na::Vector3::y());
+        let basis = camera.basis();
+        assert_orthonormal(basis);
+
+        let (_, up, backward) = basis;
+        let forward = (camera.target - camera.eye).normalize();
+        assert!((backward + forward).norm() < 1e-12);
+        assert!(up.y > 0.0);
+    }
+
+    #[test]
+    fn up_parallel_to_view_direction_is_repaired() {
+        for up in &[na::Vector3::y(), -na::Vector3::y(), na::Vector3::zeros()] {
+            let camera = camera(na::Point3::new(1.0, -5.0, 3.0), *up);
+            assert_orthonormal(camera.basis());
+        }
+    }
+}
@@ -1,30 +1,222 @@
+use std::sync::OnceLock;
+
 use image::{Rgb, Rgba};
 use nalgebra as na;
 use num::ToPrimitive;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+// how the values stored in an image relate to light
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    // encoded with the srgb transfer function, like most color textures and pngs
+    Srgb,
+    // stored as is, for data such as normal or roughness maps
+    Linear,
+}
 
-const GAMMA: f64 = 2.2;
+// the red, green and blue of the output image, all with a d65 white point. rendering
+// happens in rec. 709 and the result is converted before encoding with the srgb curve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Primaries {
+    #[default]
+    Rec709,
+    DisplayP3,
+    Rec2020,
+}
+
+impl Primaries {
+    pub fn from_rec709<T>(self, linear: na::Vector3<T>) -> na::Vector3<T>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let matrix = match self {
+            Primaries::Rec709 => return linear,
+            Primaries::DisplayP3 => na::Matrix3::new(
+                0.8224621, 0.1775380, 0.0, 0.0331941, 0.9668058, 0.0, 0.0170827, 0.0723974,
+                0.9105199,
+            ),
+            Primaries::Rec2020 => na::Matrix3::new(
+                0.6274040, 0.3292820, 0.0433136, 0.0690970, 0.9195400, 0.0113612, 0.0163916,
+                0.0880132, 0.8955950,
+            ),
+        };
+        matrix.map(|c: f64| na::convert::<f64, T>(c)) * linear
+    }
+}
 
-pub fn gamma_encode<T>(linear: na::Vector3<T>) -> na::Vector3<T>
+// the srgb oetf, from linear light to encoded values
+pub fn srgb_encode<T>(linear: na::Vector3<T>) -> na::Vector3<T>
 where
     T: na::RealField + ToPrimitive,
 {
-    linear.apply_into(|l| l.powf(T::one() / T::from_f64(GAMMA).unwrap()))
+    linear.map(|l| {
+        if l <= na::convert(0.0031308) {
+            l * na::convert(12.92)
+        } else {
+            l.powf(na::convert(1.0 / 2.4)) * na::convert(1.055) - na::convert(0.055)
+        }
+    })
 }
 
-pub fn gamma_decode<T>(encoded: na::Vector3<T>) -> na::Vector3<T>
+// the srgb eotf, from encoded values to linear light
+pub fn srgb_decode<T>(encoded: na::Vector3<T>) -> na::Vector3<T>
 where
     T: na::RealField + ToPrimitive,
 {
-    encoded.apply_into(|e| e.powf(T::from_f64(GAMMA).unwrap()))
+    encoded.map(|e| {
+        if e <= na::convert(0.04045) {
+            e / na::convert(12.92)
+        } else {
+            ((e + na::convert(0.055)) / na::convert(1.055)).powf(na::convert(2.4))
+        }
+    })
 }
 
-pub fn vec3_to_rgb<T>(mut vec: na::Vector3<T>) -> Rgb<u8>
+// relative luminance of linear rec. 709 values
+pub fn luminance<T>(color: &na::Vector3<T>) -> T
+where
+    T: na::RealField + ToPrimitive,
+{
+    color.dot(&na::Vector3::new(
+        na::convert(0.2126),
+        na::convert(0.7152),
+        na::convert(0.0722),
+    ))
+}
+
+// breaks up banding in smooth gradients by nudging each pixel before it is rounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dither {
+    #[default]
+    None,
+    // a tiled 4x4 bayer matrix
+    Ordered,
+    // a tiled void and cluster mask, which has no visible pattern
+    BlueNoise,
+}
+
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+const BLUE_NOISE_SIZE: usize = 32;
+
+impl Dither {
+    // in units of one 8-bit step, within [-0.5, 0.5)
+    pub fn offset(self, x: u32, y: u32) -> f32 {
+        let (x, y) = (x as usize, y as usize);
+        let (rank, levels) = match self {
+            Dither::None => return 0.0,
+            Dither::Ordered => (u32::from(BAYER[y % 4][x % 4]), 16),
+            Dither::BlueNoise => {
+                let mask = BLUE_NOISE.get_or_init(void_and_cluster);
+                let index = (y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE;
+                (mask[index], BLUE_NOISE_SIZE * BLUE_NOISE_SIZE)
+            }
+        };
+        (rank as f32 + 0.5) / levels as f32 - 0.5
+    }
+}
+
+static BLUE_NOISE: OnceLock<Vec<u32>> = OnceLock::new();
+
+// adds or removes a point's gaussian footprint on the wrapping energy grid
+fn splat(energy: &mut [f64], kernel: &[f64], index: usize, sign: f64) {
+    let size = BLUE_NOISE_SIZE;
+    let (px, py) = (index % size, index / size);
+    for (i, e) in energy.iter_mut().enumerate() {
+        let dx = (i % size + size - px) % size;
+        let dy = (i / size + size - py) % size;
+        *e += sign * kernel[dy * size + dx];
+    }
+}
+
+// the point whose neighborhood is most crowded, or the empty spot that is least crowded
+fn tightest_cluster(energy: &[f64], pattern: &[bool]) -> usize {
+    (0..energy.len())
+        .filter(|&i| pattern[i])
+        .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        .unwrap()
+}
+
+fn largest_void(energy: &[f64], pattern: &[bool]) -> usize {
+    (0..energy.len())
+        .filter(|&i| !pattern[i])
+        .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        .unwrap()
+}
+
+// ulichney's void and cluster method, ranking every pixel of the mask
+fn void_and_cluster() -> Vec<u32> {
+    const SIGMA: f64 = 1.5;
+    let size = BLUE_NOISE_SIZE;
+    let cells = size * size;
+    let kernel = (0..cells)
+        .map(|i| {
+            let dx = (i % size).min(size - i % size) as f64;
+            let dy = (i / size).min(size - i / size) as f64;
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect::<Vec<_>>();
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut pattern = vec![false; cells];
+    let mut energy = vec![0.0; cells];
+    let mut placed = 0;
+    while placed < cells / 10 {
+        let index = rng.gen_range(0..cells);
+        if !pattern[index] {
+            pattern[index] = true;
+            splat(&mut energy, &kernel, index, 1.0);
+            placed += 1;
+        }
+    }
+
+    // spread the initial points out evenly
+    loop {
+        let cluster = tightest_cluster(&energy, &pattern);
+        pattern[cluster] = false;
+        splat(&mut energy, &kernel, cluster, -1.0);
+        let void = largest_void(&energy, &pattern);
+        pattern[void] = true;
+        splat(&mut energy, &kernel, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; cells];
+    let ones = pattern.iter().filter(|&&p| p).count();
+
+    let (mut removed, mut removed_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..ones).rev() {
+        let cluster = tightest_cluster(&removed_energy, &removed);
+        removed[cluster] = false;
+        splat(&mut removed_energy, &kernel, cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+
+    for rank in ones..cells {
+        let void = largest_void(&energy, &pattern);
+        pattern[void] = true;
+        splat(&mut energy, &kernel, void, 1.0);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
+
+// never panics: nan becomes black and everything else is clamped to the displayable range
+pub fn vec3_to_rgb<T>(mut vec: na::Vector3<T>, dither: T) -> Rgb<u8>
 where
     T: na::RealField + ToPrimitive,
 {
     let u8_max = T::from_u8(u8::MAX).unwrap();
-    vec = gamma_encode(vec);
-    vec *= u8_max;
+    // max and min discard nan, where clamp would keep it
+    vec = vec.map(|e| e.max(T::zero()).min(T::one()));
+    vec = srgb_encode(vec) * u8_max + na::Vector3::repeat(dither);
+    vec = vec.map(|e| e.round().max(T::zero()).min(u8_max));
     Rgb([
         vec.x.to_u8().unwrap(),
         vec.y.to_u8().unwrap(),
@@ -33,7 +225,7 @@ where
 }
 
 // drops alpha component
-pub fn rgba_to_vec3<T>(rgba: &Rgba<u8>) -> na::Vector3<T>
+pub fn rgba_to_vec3<T>(rgba: &Rgba<u8>, color_space: ColorSpace) -> na::Vector3<T>
 where
     T: na::RealField + ToPrimitive,
 {
@@ -42,5 +234,73 @@ where
     let y = T::from_u8(rgba[1]).unwrap() / u8_max;
     let z = T::from_u8(rgba[2]).unwrap() / u8_max;
     let vec = na::Vector3::new(x, y, z);
-    gamma_decode(vec)
+    match color_space {
+        ColorSpace::Srgb => srgb_decode(vec),
+        ColorSpace::Linear => vec,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips_through_eight_bits() {
+        for value in 0..=u8::MAX {
+            let linear = rgba_to_vec3::<f64>(&Rgba([value, value, value, 255]), ColorSpace::Srgb);
+            assert_eq!(vec3_to_rgb(linear, 0.0), Rgb([value, value, value]));
+        }
+
+        // middle grey lands on 188, where a plain 2.2 power curve would give 186
+        assert_eq!(
+            vec3_to_rgb(na::Vector3::repeat(0.5), 0.0).0,
+            [188, 188, 188]
+        );
+        let linear = rgba_to_vec3::<f64>(&Rgba([128, 0, 255, 255]), ColorSpace::Linear);
+        assert!((linear - na::Vector3::new(128.0 / 255.0, 0.0, 1.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn wide_gamut_primaries_keep_white() {
+        let white = na::Vector3::repeat(1.0);
+        for &primaries in &[Primaries::Rec709, Primaries::DisplayP3, Primaries::Rec2020] {
+            assert!((primaries.from_rec709(white) - white).norm() < 1e-5);
+        }
+
+        // pure rec. 709 red sits inside the wider gamuts
+        let red = Primaries::Rec2020.from_rec709(na::Vector3::new(1.0, 0.0, 0.0));
+        assert!(red.x < 1.0 && red.y > 0.0 && red.z > 0.0);
+    }
+
+    #[test]
+    fn quantization_handles_invalid_values_and_dithers_to_the_mean() {
+        let invalid = na::Vector3::new(f64::NAN, f64::INFINITY, f64::NEG_INFINITY);
+        assert_eq!(vec3_to_rgb(invalid, 0.0).0, [0, 255, 0]);
+        assert_eq!(vec3_to_rgb(na::Vector3::repeat(1e30), 0.49).0, [255; 3]);
+        assert_eq!(vec3_to_rgb(na::Vector3::repeat(-1.0), -0.5).0, [0; 3]);
+
+        // a level a quarter of the way between two 8-bit steps
+        let level = srgb_decode(na::Vector3::repeat(100.25 / 255.0)).map(|c: f64| c as f32);
+        for &(dither, size) in &[(Dither::Ordered, 4), (Dither::BlueNoise, BLUE_NOISE_SIZE)] {
+            let mut sum = 0;
+            for y in 0..size as u32 {
+                for x in 0..size as u32 {
+                    sum += u32::from(vec3_to_rgb(level, dither.offset(x, y))[0]);
+                }
+            }
+            let mean = sum as f32 / (size * size) as f32;
+            assert!(
+                (mean - 100.25).abs() < 0.01,
+                "{:?} averaged {}",
+                dither,
+                mean
+            );
+        }
+
+        let mut ranks = BLUE_NOISE.get_or_init(void_and_cluster).clone();
+        ranks.sort_unstable();
+        assert!(ranks.iter().enumerate().all(|(i, &r)| r == i as u32));
+    }
 }
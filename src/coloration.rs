@@ -1,13 +1,13 @@
-use std::fmt::Debug;
+use std::{any::Any, fmt::Debug, path::PathBuf};
 
 use image::{DynamicImage, GenericImageView};
 use na::RealField;
 use nalgebra as na;
 use num::ToPrimitive;
 
-use crate::color_convert::rgba_to_vec3;
+use crate::color_convert::{rgba_to_vec3, ColorSpace};
 
-pub trait Coloration<T>: Debug + Send + Sync
+pub trait Coloration<T>: Any + Debug + Send + Sync
 where
     T: RealField + ToPrimitive,
 {
@@ -48,6 +48,9 @@ where
 #[derive(Debug)]
 pub struct Texture {
     pub texture: DynamicImage,
+    pub color_space: ColorSpace,
+    // where the texture was loaded from, needed to write it to a scene file
+    pub path: Option<PathBuf>,
 }
 
 impl<T> Coloration<T> for Texture
@@ -58,6 +61,6 @@ where
         let tex_x = wrap(texture_coords.x, self.texture.width());
         let tex_y = wrap(texture_coords.y, self.texture.height());
 
-        rgba_to_vec3(&self.texture.get_pixel(tex_x, tex_y))
+        rgba_to_vec3(&self.texture.get_pixel(tex_x, tex_y), self.color_space)
     }
 }
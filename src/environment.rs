@@ -0,0 +1,339 @@
+use std::{
+    any::Any,
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
+
+use image::Rgb32FImage;
+use nalgebra as na;
+use num::ToPrimitive;
+
+use crate::{color_convert::luminance, sampling::uniform_sphere};
+
+// light arriving from infinitely far away, seen by rays that miss every object
+pub trait Environment<T>: Any + Debug + Send + Sync
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn radiance(&self, direction: &na::Vector3<T>) -> na::Vector3<T>;
+
+    // a direction chosen by a sample in the unit square along with its solid angle pdf
+    fn sample(&self, sample: &na::Vector2<T>) -> (na::Vector3<T>, T) {
+        let four = T::from_f64(4.0).unwrap();
+        (uniform_sphere(sample), T::one() / (four * T::pi()))
+    }
+}
+
+#[derive(Debug)]
+pub struct ConstantEnvironment<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub color: na::Vector3<T>,
+}
+
+impl<T> Environment<T> for ConstantEnvironment<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn radiance(&self, _direction: &na::Vector3<T>) -> na::Vector3<T> {
+        self.color
+    }
+}
+
+// blends from the horizon towards the zenith above it and the ground below it
+#[derive(Debug)]
+pub struct GradientEnvironment<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub zenith: na::Vector3<T>,
+    pub horizon: na::Vector3<T>,
+    pub ground: na::Vector3<T>,
+}
+
+impl<T> Environment<T> for GradientEnvironment<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn radiance(&self, direction: &na::Vector3<T>) -> na::Vector3<T> {
+        let height = direction.normalize().y;
+        if height >= T::zero() {
+            self.horizon.lerp(&self.zenith, height)
+        } else {
+            self.horizon.lerp(&self.ground, -height)
+        }
+    }
+}
+
+// piecewise constant distribution over a grid, sampled by picking a row then a column
+#[derive(Debug)]
+struct Distribution2D<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    width: usize,
+    height: usize,
+    // running sums within each row, and over the row totals
+    conditional: Vec<T>,
+    marginal: Vec<T>,
+}
+
+// index of the bucket a sample falls into and how far through it the sample is
+fn sample_cdf<T>(cdf: &[T], sample: T) -> (usize, T)
+where
+    T: na::RealField + ToPrimitive,
+{
+    let total = cdf[cdf.len() - 1];
+    let target = sample * total;
+    let index = cdf.partition_point(|&c| c <= target).min(cdf.len() - 1);
+    let start = if index == 0 {
+        T::zero()
+    } else {
+        cdf[index - 1]
+    };
+    let width = cdf[index] - start;
+    let offset = if width > T::zero() {
+        (target - start) / width
+    } else {
+        T::zero()
+    };
+    (index, offset)
+}
+
+impl<T> Distribution2D<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn new(weights: &[T], width: usize, height: usize) -> Distribution2D<T> {
+        let mut conditional = Vec::with_capacity(width * height);
+        let mut marginal = Vec::with_capacity(height);
+        let mut total = T::zero();
+        for row in weights.chunks_exact(width) {
+            let mut sum = T::zero();
+            for &weight in row {
+                sum += weight;
+                conditional.push(sum);
+            }
+            total += sum;
+            marginal.push(total);
+        }
+
+        Distribution2D {
+            width,
+            height,
+            conditional,
+            marginal,
+        }
+    }
+
+    fn total(&self) -> T {
+        self.marginal[self.height - 1]
+    }
+
+    // continuous position in [0, 1)^2 and the density there relative to the unit square
+    fn sample(&self, sample: &na::Vector2<T>) -> (na::Vector2<T>, T) {
+        let (row, row_offset) = sample_cdf(&self.marginal, sample.y);
+        let row_cdf = &self.conditional[row * self.width..(row + 1) * self.width];
+        let (column, column_offset) = sample_cdf(row_cdf, sample.x);
+
+        let (width, height) = (
+            T::from_usize(self.width).unwrap(),
+            T::from_usize(self.height).unwrap(),
+        );
+        let position = na::Vector2::new(
+            (T::from_usize(column).unwrap() + column_offset) / width,
+            (T::from_usize(row).unwrap() + row_offset) / height,
+        );
+        (position, self.pdf(row, column))
+    }
+
+    fn pdf(&self, row: usize, column: usize) -> T {
+        let index = row * self.width + column;
+        let weight = if column == 0 {
+            self.conditional[index]
+        } else {
+            self.conditional[index] - self.conditional[index - 1]
+        };
+        let cells = T::from_usize(self.width * self.height).unwrap();
+        weight * cells / self.total()
+    }
+}
+
+// an equirectangular image, importance sampled by brightness
+#[derive(Debug)]
+pub struct ImageEnvironment<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    width: usize,
+    height: usize,
+    pixels: Vec<na::Vector3<T>>,
+    distribution: Option<Distribution2D<T>>,
+    // where the image was loaded from, needed to write it to a scene file
+    pub(crate) path: Option<PathBuf>,
+    pub intensity: T,
+}
+
+impl<T> ImageEnvironment<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    // loads any image format, though radiance .hdr and openexr files keep the full range
+    pub fn open<P: AsRef<Path>>(path: P, intensity: T) -> image::ImageResult<ImageEnvironment<T>> {
+        let mut environment = Self::from_image(&image::open(&path)?.into_rgb32f(), intensity);
+        environment.path = Some(path.as_ref().into());
+        Ok(environment)
+    }
+
+    pub fn from_image(image: &Rgb32FImage, intensity: T) -> ImageEnvironment<T> {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image
+            .pixels()
+            .map(|p| na::Vector3::new(p[0], p[1], p[2]).map(|c| na::convert(f64::from(c))))
+            .collect::<Vec<na::Vector3<T>>>();
+
+        // rows near the poles cover less solid angle
+        let weights = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let v = (T::from_usize(i / width).unwrap() + na::convert(0.5))
+                    / T::from_usize(height).unwrap();
+                luminance(p).max(T::zero()) * (v * T::pi()).sin()
+            })
+            .collect::<Vec<_>>();
+        let distribution = Distribution2D::new(&weights, width, height);
+        let distribution = if distribution.total() > T::zero() {
+            Some(distribution)
+        } else {
+            None
+        };
+
+        ImageEnvironment {
+            width,
+            height,
+            pixels,
+            distribution,
+            path: None,
+            intensity,
+        }
+    }
+
+    fn pixel_at(&self, uv: &na::Vector2<T>) -> (usize, usize) {
+        let column = (uv.x * T::from_usize(self.width).unwrap())
+            .to_usize()
+            .unwrap_or(0)
+            .min(self.width - 1);
+        let row = (uv.y * T::from_usize(self.height).unwrap())
+            .to_usize()
+            .unwrap_or(0)
+            .min(self.height - 1);
+        (row, column)
+    }
+}
+
+// u wraps around the horizon starting behind the default camera, v runs from the zenith down
+fn direction_to_uv<T>(direction: &na::Vector3<T>) -> na::Vector2<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let direction = direction.normalize();
+    let half = T::from_f64(0.5).unwrap();
+    let u = half + direction.x.atan2(-direction.z) / T::two_pi();
+    let v = direction.y.max(-T::one()).min(T::one()).acos() / T::pi();
+    na::Vector2::new(u, v)
+}
+
+fn uv_to_direction<T>(uv: &na::Vector2<T>) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let half = T::from_f64(0.5).unwrap();
+    let phi = (uv.x - half) * T::two_pi();
+    let theta = uv.y * T::pi();
+    na::Vector3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+impl<T> Environment<T> for ImageEnvironment<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn radiance(&self, direction: &na::Vector3<T>) -> na::Vector3<T> {
+        let (row, column) = self.pixel_at(&direction_to_uv(direction));
+        self.pixels[row * self.width + column] * self.intensity
+    }
+
+    fn sample(&self, sample: &na::Vector2<T>) -> (na::Vector3<T>, T) {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => {
+                let four = T::from_f64(4.0).unwrap();
+                return (uniform_sphere(sample), T::one() / (four * T::pi()));
+            }
+        };
+
+        let (uv, pdf) = distribution.sample(sample);
+        let sin_theta = (uv.y * T::pi()).sin();
+        if sin_theta <= T::zero() {
+            return (uv_to_direction(&uv), T::zero());
+        }
+
+        // convert from density over the image to density over solid angle
+        let two = T::from_f64(2.0).unwrap();
+        (
+            uv_to_direction(&uv),
+            pdf / (two * T::pi() * T::pi() * sin_theta),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+    use nalgebra as na;
+
+    use super::*;
+
+    #[test]
+    fn image_sampling_favors_bright_pixels() {
+        let mut image = Rgb32FImage::from_pixel(32, 16, Rgb([0.1, 0.1, 0.1]));
+        image.put_pixel(20, 5, Rgb([1000.0, 1000.0, 1000.0]));
+        let environment = ImageEnvironment::<f64>::from_image(&image, 1.0);
+
+        let bright = uv_to_direction(&na::Vector2::new(20.5 / 32.0, 5.5 / 16.0));
+        assert_eq!(environment.radiance(&bright), na::Vector3::repeat(1000.0));
+
+        let mut hits = 0;
+        for i in 0..16 {
+            for j in 0..16 {
+                let sample =
+                    na::Vector2::new((f64::from(i) + 0.5) / 16.0, (f64::from(j) + 0.5) / 16.0);
+                let (direction, pdf) = environment.sample(&sample);
+                assert!(pdf > 0.0);
+                assert!((direction.norm() - 1.0).abs() < 1e-9);
+                if environment.radiance(&direction).x > 1.0 {
+                    hits += 1;
+                }
+            }
+        }
+        assert!(
+            hits > 128,
+            "only {} of 256 samples found the bright pixel",
+            hits
+        );
+    }
+
+    #[test]
+    fn uv_mapping_round_trips() {
+        for &(u, v) in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.3, 0.95)] {
+            let uv = na::Vector2::new(u, v);
+            let back = direction_to_uv(&uv_to_direction(&uv));
+            assert!((back - uv).norm() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use image::{Luma, Rgb, Rgb32FImage};
+use nalgebra as na;
+
+use crate::{filter::Filter, output::SampleCounts, tiles::Tile};
+
+// pixels darker than this are judged by their absolute error, so a little noise in the shadows
+// does not keep them sampling forever
+const ERROR_FLOOR: f64 = 1e-2;
+
+// running mean and variance of a pixel's luminance (welford), which can be merged across passes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct PixelStats {
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl PixelStats {
+    pub(crate) fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / f64::from(self.count);
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // chan et al.'s update for combining two independent sets of samples
+    pub(crate) fn merge(&mut self, other: &PixelStats) {
+        if other.count == 0 {
+            return;
+        }
+        let (a, b) = (f64::from(self.count), f64::from(other.count));
+        let delta = other.mean - self.mean;
+        self.mean += delta * b / (a + b);
+        self.m2 += other.m2 + delta * delta * a * b / (a + b);
+        self.count += other.count;
+    }
+
+    pub(crate) fn count(&self) -> u32 {
+        self.count
+    }
+
+    // standard error of the mean relative to the mean, unknown (infinite) before two samples
+    pub(crate) fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let count = f64::from(self.count);
+        let variance = self.m2 / (count - 1.0);
+        (variance / count).sqrt() / self.mean.abs().max(ERROR_FLOOR)
+    }
+}
+
+// a worker's own patch of the film, reaching past its tile far enough to take every pixel its
+// samples are splatted onto
+#[derive(Debug, Clone)]
+pub(crate) struct FilmTile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    filter: Filter,
+    sums: Vec<na::Vector3<f64>>,
+    weights: Vec<f64>,
+}
+
+impl FilmTile {
+    pub(crate) fn new(tile: Tile, image_width: u32, image_height: u32, filter: Filter) -> FilmTile {
+        let margin = (filter.radius + 0.5).ceil().max(0.0) as u32;
+        let (x, y) = (tile.x.saturating_sub(margin), tile.y.saturating_sub(margin));
+        let width = (tile.x + tile.width + margin).min(image_width) - x;
+        let height = (tile.y + tile.height + margin).min(image_height) - y;
+        let pixels = (width * height) as usize;
+        FilmTile {
+            x,
+            y,
+            width,
+            height,
+            filter,
+            sums: vec![na::Vector3::zeros(); pixels],
+            weights: vec![0.0; pixels],
+        }
+    }
+
+    // adds a sample taken at the given point of the image, in pixels from the top left, to every
+    // pixel the filter reaches
+    pub(crate) fn add_sample(&mut self, position: na::Point2<f64>, color: &na::Vector3<f64>) {
+        let (x0, x1) = self.filter.footprint(position.x);
+        let (y0, y1) = self.filter.footprint(position.y);
+        let clamp = |v: i64, start: u32, length: u32| {
+            v.clamp(i64::from(start), i64::from(start + length)) as u32
+        };
+        for y in clamp(y0, self.y, self.height)..clamp(y1 + 1, self.y, self.height) {
+            for x in clamp(x0, self.x, self.width)..clamp(x1 + 1, self.x, self.width) {
+                let weight = self.filter.weight(
+                    f64::from(x) + 0.5 - position.x,
+                    f64::from(y) + 0.5 - position.y,
+                );
+                let index = ((y - self.y) * self.width + (x - self.x)) as usize;
+                self.sums[index] += color * weight;
+                self.weights[index] += weight;
+            }
+        }
+    }
+}
+
+// filtered sums of radiance samples for every pixel, normalized when an image is needed, and
+// statistics of the samples taken in each pixel
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Film {
+    width: u32,
+    height: u32,
+    sums: Vec<na::Vector3<f64>>,
+    weights: Vec<f64>,
+    stats: Vec<PixelStats>,
+}
+
+impl Film {
+    pub(crate) fn new(width: u32, height: u32) -> Film {
+        let pixels = (width * height) as usize;
+        Film {
+            width,
+            height,
+            sums: vec![na::Vector3::zeros(); pixels],
+            weights: vec![0.0; pixels],
+            stats: vec![PixelStats::default(); pixels],
+        }
+    }
+
+    pub(crate) fn add_tile(&mut self, tile: &FilmTile) {
+        for y in 0..tile.height {
+            for x in 0..tile.width {
+                let from = (y * tile.width + x) as usize;
+                let to = ((tile.y + y) * self.width + tile.x + x) as usize;
+                self.sums[to] += tile.sums[from];
+                self.weights[to] += tile.weights[from];
+            }
+        }
+    }
+
+    pub(crate) fn add_stats(&mut self, x: u32, y: u32, stats: &PixelStats) {
+        self.stats[(y * self.width + x) as usize].merge(stats);
+    }
+
+    pub(crate) fn stats(&self, x: u32, y: u32) -> PixelStats {
+        self.stats[(y * self.width + x) as usize]
+    }
+
+    pub(crate) fn to_image(&self) -> Rgb32FImage {
+        Rgb32FImage::from_fn(self.width, self.height, |x, y| {
+            let index = (y * self.width + x) as usize;
+            // filters with negative lobes can leave a pixel without any weight
+            let weight = self.weights[index];
+            if weight != 0.0 {
+                Rgb((self.sums[index] / weight).map(|c| c as f32).into())
+            } else {
+                Rgb([0.0; 3])
+            }
+        })
+    }
+
+    pub(crate) fn sample_counts(&self) -> SampleCounts {
+        SampleCounts::from_fn(self.width, self.height, |x, y| {
+            Luma([self.stats(x, y).count()])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_stats_match_one_pass_over_all_samples() {
+        let values = [0.5, 2.0, 0.25, 1.0, 3.5, 0.0, 1.25];
+        let mut all = PixelStats::default();
+        values.iter().for_each(|&v| all.add(v));
+
+        let (mut first, mut second) = (PixelStats::default(), PixelStats::default());
+        values[..3].iter().for_each(|&v| first.add(v));
+        values[3..].iter().for_each(|&v| second.add(v));
+        first.merge(&second);
+
+        assert_eq!(first.count(), all.count());
+        assert!((first.mean - all.mean).abs() < 1e-12);
+        assert!((first.m2 - all.m2).abs() < 1e-12);
+
+        let mut flat = PixelStats::default();
+        (0..4).for_each(|_| flat.add(0.7));
+        assert_eq!(flat.relative_error(), 0.0);
+        assert_eq!(PixelStats::default().relative_error(), f64::INFINITY);
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+// how much a sample counts towards a pixel, by its distance from the pixel center
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterShape {
+    // equal weight everywhere, which with a radius of half a pixel averages each pixel's own
+    // samples and nothing else
+    Box,
+    // falls off linearly to zero at the radius
+    Tent,
+    // exp(-alpha * d^2), shifted down to reach zero at the radius
+    Gaussian { alpha: f64 },
+    // mitchell and netravali's cubic, b = c = 1/3 is their recommendation. sharpens with
+    // slightly negative lobes
+    MitchellNetravali { b: f64, c: f64 },
+    // sinc windowed by a wider sinc with tau lobes, the sharpest and the most prone to ringing
+    Lanczos { tau: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+    // in pixels, along each axis
+    pub radius: f64,
+    pub shape: FilterShape,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            radius: 0.5,
+            shape: FilterShape::Box,
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+impl Filter {
+    // separable, so the weight of an offset is the product of the weights along each axis
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f64) -> f64 {
+        let (d, radius) = (d.abs(), self.radius);
+        if d > radius {
+            return 0.0;
+        }
+        match self.shape {
+            FilterShape::Box => 1.0,
+            FilterShape::Tent => 1.0 - d / radius,
+            FilterShape::Gaussian { alpha } => {
+                ((-alpha * d * d).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            FilterShape::MitchellNetravali { b, c } => {
+                // the cubic is defined over [0, 2]
+                let x = 2.0 * d / radius;
+                let weight = if x > 1.0 {
+                    (-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                } else {
+                    (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b)
+                };
+                weight / 6.0
+            }
+            FilterShape::Lanczos { tau } => sinc(d) * sinc(d / tau),
+        }
+    }
+
+    // the pixels whose centers are within the radius of a position along one axis. the range is
+    // half open, so a sample on the border between two pixels only counts towards one of them
+    pub(crate) fn footprint(&self, position: f64) -> (i64, i64) {
+        (
+            (position - 0.5 - self.radius).floor() as i64 + 1,
+            (position - 0.5 + self.radius).floor() as i64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_peak_at_the_center_and_vanish_past_the_radius() {
+        let shapes = [
+            FilterShape::Box,
+            FilterShape::Tent,
+            FilterShape::Gaussian { alpha: 2.0 },
+            FilterShape::MitchellNetravali {
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+            FilterShape::Lanczos { tau: 3.0 },
+        ];
+        for shape in shapes {
+            let filter = Filter { radius: 2.0, shape };
+            let center = filter.weight(0.0, 0.0);
+            assert!(center > 0.0, "{:?}", shape);
+            for i in 1..40 {
+                let d = f64::from(i) * 0.05;
+                assert!(filter.weight(d, 0.0) <= center, "{:?} at {}", shape, d);
+                assert_eq!(filter.weight(d, 0.0), filter.weight(-d, 0.0));
+            }
+            assert_eq!(filter.weight(2.01, 0.0), 0.0, "{:?}", shape);
+            assert_eq!(filter.weight(0.0, -2.5), 0.0, "{:?}", shape);
+        }
+
+        let mitchell = Filter {
+            radius: 2.0,
+            shape: FilterShape::MitchellNetravali {
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+        };
+        assert!(
+            mitchell.weight(1.5, 0.0) < 0.0,
+            "mitchell should have a negative lobe"
+        );
+
+        // a box of half a pixel only reaches the pixel the sample is in
+        let filter = Filter::default();
+        assert_eq!(filter.footprint(3.0), (3, 3));
+        assert_eq!(filter.footprint(3.99), (3, 3));
+        assert_eq!(
+            Filter {
+                radius: 1.5,
+                ..filter
+            }
+            .footprint(3.2),
+            (2, 4)
+        );
+    }
+}
@@ -16,7 +16,7 @@ impl<T> Intersection<'_, T>
 where
     T: na::RealField + ToPrimitive,
 {
-    pub fn new(distance: T, object: &dyn Intersectable<T>) -> Intersection<T> {
+    pub fn new(distance: T, object: &dyn Intersectable<T>) -> Intersection<'_, T> {
         Intersection { distance, object }
     }
 }
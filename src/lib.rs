@@ -1,25 +1,44 @@
 pub use nalgebra;
 
-mod color_convert;
+mod film;
 mod intersection;
 mod ray;
 mod render;
+mod sampling;
 mod scene;
 
+pub mod bvh;
+pub mod camera;
+pub mod color_convert;
 pub mod coloration;
+pub mod environment;
+pub mod filter;
 pub mod lights;
 pub mod material;
+pub mod obj;
 pub mod objects;
+pub mod output;
+pub mod progress;
+pub mod sampler;
+pub mod scene_file;
+pub mod sky;
+pub mod tiles;
+pub mod tone_mapping;
 
+pub use camera::Camera;
 pub use material::Material;
-pub use render::render;
-pub use scene::Scene;
+pub use render::{
+    develop, render, render_hdr, render_hdr_with, render_hdr_with_sample_counts,
+    render_in_background, render_with, render_with_report, AdaptiveSampling, RenderError,
+    RenderHandle, RenderMode, RenderOptions, RenderReport,
+};
+pub use scene::{Integrator, Scene};
 
 #[cfg(test)]
 mod tests {
     use nalgebra as na;
 
-    use super::{coloration::*, lights::*, material::*, *};
+    use super::{camera::FovAxis, color_convert::*, coloration::*, lights::*, material::*, *};
 
     use objects::*;
 
@@ -28,93 +47,119 @@ mod tests {
         let checkerboard =
             image::open("checkerboard.png").expect("failed to open checkerboard texture!");
 
-        let scene = Scene {
-            width: 800,
-            height: 600,
-            samples: 4,
-            fov: 90.0,
-            objects: vec![
-                Box::new(Sphere {
-                    center: na::Point3::new(0.0, 0.0, -5.0),
-                    radius: 1.0,
-                    material: Material {
-                        color: Box::new(Color {
-                            color: na::Vector3::new(0.4, 1.0, 0.4),
-                        }),
-                        surface: SurfaceType::Reflective { reflectivity: 0.7 },
-                        albedo: 0.18,
+        let mut scene = Scene::new(
+            800,
+            600,
+            Camera {
+                eye: na::Point3::origin(),
+                target: na::Point3::new(0.0, 0.0, -1.0),
+                up: na::Vector3::new(0.0, 1.0, 0.0),
+                fov: 90.0,
+                fov_axis: FovAxis::Vertical,
+                aperture: 0.0,
+                focus_distance: 5.0,
+            },
+        );
+        scene.samples = 4;
+        scene.objects = vec![
+            Box::new(Sphere {
+                center: na::Point3::new(0.0, 0.0, -5.0),
+                radius: 1.0,
+                material: Material {
+                    color: Box::new(Color {
+                        color: na::Vector3::new(0.4, 1.0, 0.4),
+                    }),
+                    surface: SurfaceType::Reflective { reflectivity: 0.7 },
+                    albedo: 0.18,
+                    emission: na::Vector3::zeros(),
+                    emission_strength: 0.0,
+                },
+            }),
+            Box::new(Sphere {
+                center: na::Point3::new(-3.0, 1.0, -6.0),
+                radius: 2.0,
+                material: Material {
+                    color: Box::new(Texture {
+                        texture: checkerboard.clone(),
+                        color_space: ColorSpace::Srgb,
+                        path: None,
+                    }),
+                    surface: SurfaceType::Diffuse,
+                    albedo: 0.58,
+                    emission: na::Vector3::zeros(),
+                    emission_strength: 0.0,
+                },
+            }),
+            Box::new(Sphere {
+                center: na::Point3::new(2.0, 1.0, -4.0),
+                radius: 1.5,
+                material: Material {
+                    color: Box::new(Color {
+                        color: na::Vector3::new(1.0, 1.0, 1.0),
+                    }),
+                    surface: SurfaceType::Refractive {
+                        index: 1.5,
+                        transparency: 1.0,
                     },
-                }),
-                Box::new(Sphere {
-                    center: na::Point3::new(-3.0, 1.0, -6.0),
-                    radius: 2.0,
-                    material: Material {
-                        color: Box::new(Texture {
-                            texture: checkerboard.clone(),
-                        }),
-                        surface: SurfaceType::Diffuse,
-                        albedo: 0.58,
-                    },
-                }),
-                Box::new(Sphere {
-                    center: na::Point3::new(2.0, 1.0, -4.0),
-                    radius: 1.5,
-                    material: Material {
-                        color: Box::new(Color {
-                            color: na::Vector3::new(1.0, 1.0, 1.0),
-                        }),
-                        surface: SurfaceType::Refractive {
-                            index: 1.5,
-                            transparency: 1.0,
-                        },
-                        albedo: 0.18,
-                    },
-                }),
-                Box::new(Plane {
-                    origin: na::Point3::new(0.0, 0.0, -20.0),
-                    normal: na::Vector3::new(0.0, 0.0, -1.0),
-                    material: Material {
-                        color: Box::new(Color {
-                            color: na::Vector3::new(0.6, 0.8, 1.0),
-                        }),
-                        surface: SurfaceType::Diffuse,
-                        albedo: 0.18,
-                    },
-                }),
-                Box::new(Plane {
-                    origin: na::Point3::new(0.0, -2.0, 0.0),
-                    normal: na::Vector3::new(0.0, -1.0, 0.0),
-                    material: Material {
-                        color: Box::new(Texture {
-                            texture: checkerboard,
-                        }),
-                        surface: SurfaceType::Reflective { reflectivity: 0.5 },
-                        albedo: 0.18,
-                    },
-                }),
-            ],
-            lights: vec![
-                Box::new(SphericalLight {
-                    position: na::Point3::new(-2.0, 10.0, -3.0),
-                    color: na::Vector3::new(0.3, 0.8, 0.3),
-                    intensity: 10000.0,
-                }),
-                Box::new(SphericalLight {
-                    position: na::Point3::new(0.25, 0.0, -2.0),
-                    color: na::Vector3::new(0.8, 0.3, 0.3),
-                    intensity: 1000.0,
-                }),
-                Box::new(DirectionalLight {
-                    direction: na::Vector3::new(0.0, 0.0, -1.0),
-                    color: na::Vector3::new(1.0, 1.0, 1.0),
-                    intensity: 0.0,
-                }),
-            ],
-            shadow_bias: 1e-13,
-            max_recursion_depth: 20,
-        };
+                    albedo: 0.18,
+                    emission: na::Vector3::zeros(),
+                    emission_strength: 0.0,
+                },
+            }),
+            Box::new(Plane {
+                origin: na::Point3::new(0.0, 0.0, -20.0),
+                normal: na::Vector3::new(0.0, 0.0, -1.0),
+                material: Material {
+                    color: Box::new(Color {
+                        color: na::Vector3::new(0.6, 0.8, 1.0),
+                    }),
+                    surface: SurfaceType::Diffuse,
+                    albedo: 0.18,
+                    emission: na::Vector3::zeros(),
+                    emission_strength: 0.0,
+                },
+            }),
+            Box::new(Plane {
+                origin: na::Point3::new(0.0, -2.0, 0.0),
+                normal: na::Vector3::new(0.0, -1.0, 0.0),
+                material: Material {
+                    color: Box::new(Texture {
+                        texture: checkerboard,
+                        color_space: ColorSpace::Srgb,
+                        path: None,
+                    }),
+                    surface: SurfaceType::Reflective { reflectivity: 0.5 },
+                    albedo: 0.18,
+                    emission: na::Vector3::zeros(),
+                    emission_strength: 0.0,
+                },
+            }),
+        ];
+        scene.lights = vec![
+            Box::new(SphericalLight {
+                position: na::Point3::new(-2.0, 10.0, -3.0),
+                color: na::Vector3::new(0.3, 0.8, 0.3),
+                intensity: 10000.0,
+                radius: 0.0,
+                samples: 1,
+            }),
+            Box::new(SphericalLight {
+                position: na::Point3::new(0.25, 0.0, -2.0),
+                color: na::Vector3::new(0.8, 0.3, 0.3),
+                intensity: 1000.0,
+                radius: 0.0,
+                samples: 1,
+            }),
+            Box::new(DirectionalLight {
+                direction: na::Vector3::new(0.0, 0.0, -1.0),
+                color: na::Vector3::new(1.0, 1.0, 1.0),
+                intensity: 0.0,
+            }),
+        ];
+        scene.shadow_bias = 1e-13;
+        scene.max_recursion_depth = 20;
 
-        let img = render(scene);
+        let img = render(scene).unwrap();
         img.save("render.png").unwrap();
     }
 }
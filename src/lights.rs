@@ -1,9 +1,21 @@
-use std::fmt::Debug;
+use std::{any::Any, fmt::Debug};
 
 use nalgebra as na;
 use num::ToPrimitive;
 
-pub trait Light<T>: Debug + Sync + Send
+use crate::sampling::{concentric_disk, orthonormal_basis};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub direction: na::Vector3<T>,
+    pub distance: T,
+    pub intensity: T,
+}
+
+pub trait Light<T>: Any + Debug + Sync + Send
 where
     T: na::RealField + ToPrimitive,
 {
@@ -11,6 +23,20 @@ where
     fn direction_from(&self, hit_point: &na::Point3<T>) -> na::Vector3<T>;
     fn intensity(&self, hit_point: &na::Point3<T>) -> T;
     fn distance(&self, hit_point: &na::Point3<T>) -> T;
+
+    // number of shadow rays cast towards the light per shading point
+    fn shadow_samples(&self) -> u32 {
+        1
+    }
+
+    // a point on the light's surface chosen by a sample in the unit square
+    fn sample(&self, hit_point: &na::Point3<T>, _sample: &na::Vector2<T>) -> LightSample<T> {
+        LightSample {
+            direction: self.direction_from(hit_point),
+            distance: self.distance(hit_point),
+            intensity: self.intensity(hit_point),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -21,6 +47,9 @@ where
     pub position: na::Point3<T>,
     pub color: na::Vector3<T>,
     pub intensity: T,
+    // zero for a point light with hard shadows
+    pub radius: T,
+    pub samples: u32,
 }
 
 impl<T> Light<T> for SphericalLight<T>
@@ -44,6 +73,27 @@ where
     fn distance(&self, hit_point: &na::Point3<T>) -> T {
         (self.position - hit_point).magnitude()
     }
+
+    fn shadow_samples(&self) -> u32 {
+        if self.radius > T::zero() {
+            self.samples
+        } else {
+            1
+        }
+    }
+
+    // samples the disk the sphere projects to as seen from the hit point
+    fn sample(&self, hit_point: &na::Point3<T>, sample: &na::Vector2<T>) -> LightSample<T> {
+        let (tangent, bitangent) = orthonormal_basis(&self.direction_from(hit_point));
+        let offset = concentric_disk(sample) * self.radius;
+        let to_light = self.position + tangent * offset.x + bitangent * offset.y - hit_point;
+        let distance = to_light.magnitude();
+        LightSample {
+            direction: to_light / distance,
+            distance,
+            intensity: self.intensity(hit_point),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -76,3 +126,200 @@ where
         T::one() / T::zero() // infinity
     }
 }
+
+// a one sided parallelogram spanned by two edges from its center, emitting along u x v
+#[derive(Debug)]
+pub struct RectangularLight<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub position: na::Point3<T>,
+    pub u: na::Vector3<T>,
+    pub v: na::Vector3<T>,
+    pub color: na::Vector3<T>,
+    pub intensity: T,
+    pub samples: u32,
+}
+
+impl<T> RectangularLight<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn intensity_towards(&self, to_light: &na::Vector3<T>) -> T {
+        let normal = self.u.cross(&self.v).normalize();
+        let distance2 = to_light.norm_squared();
+        let cos_light = (-to_light.dot(&normal) / distance2.sqrt()).max(T::zero());
+        let four = T::from_f64(4.0).unwrap();
+        self.intensity * cos_light / (four * T::pi() * distance2)
+    }
+}
+
+impl<T> Light<T> for RectangularLight<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn color(&self) -> na::Vector3<T> {
+        self.color
+    }
+
+    fn direction_from(&self, hit_point: &na::Point3<T>) -> na::Vector3<T> {
+        (self.position - hit_point).normalize()
+    }
+
+    fn intensity(&self, hit_point: &na::Point3<T>) -> T {
+        self.intensity_towards(&(self.position - hit_point))
+    }
+
+    fn distance(&self, hit_point: &na::Point3<T>) -> T {
+        (self.position - hit_point).magnitude()
+    }
+
+    fn shadow_samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn sample(&self, hit_point: &na::Point3<T>, sample: &na::Vector2<T>) -> LightSample<T> {
+        let half = T::from_f64(0.5).unwrap();
+        let point = self.position + self.u * (sample.x - half) + self.v * (sample.y - half);
+        let to_light = point - hit_point;
+        let distance = to_light.magnitude();
+        LightSample {
+            direction: to_light / distance,
+            distance,
+            intensity: self.intensity_towards(&to_light),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SpotLight<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub position: na::Point3<T>,
+    pub direction: na::Vector3<T>,
+    pub color: na::Vector3<T>,
+    pub intensity: T,
+    // half angles of the cone in degrees, full intensity inside inner and none outside outer
+    pub inner_angle: T,
+    pub outer_angle: T,
+}
+
+impl<T> SpotLight<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn falloff(&self, hit_point: &na::Point3<T>) -> T {
+        let to_radians = T::pi() / T::from_f64(180.0).unwrap();
+        let cos_inner = (self.inner_angle * to_radians).cos();
+        let cos_outer = (self.outer_angle * to_radians).cos();
+        let cos_angle = (hit_point - self.position)
+            .normalize()
+            .dot(&self.direction.normalize());
+
+        if cos_angle >= cos_inner {
+            return T::one();
+        }
+        if cos_angle <= cos_outer {
+            return T::zero();
+        }
+
+        // smoothstep between the edges of the cone
+        let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+        t * t * (T::from_f64(3.0).unwrap() - T::from_f64(2.0).unwrap() * t)
+    }
+}
+
+impl<T> Light<T> for SpotLight<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn color(&self) -> na::Vector3<T> {
+        self.color
+    }
+
+    fn direction_from(&self, hit_point: &na::Point3<T>) -> na::Vector3<T> {
+        (self.position - hit_point).normalize()
+    }
+
+    fn intensity(&self, hit_point: &na::Point3<T>) -> T {
+        let four = T::from_f64(4.0).unwrap();
+        let r2 = (self.position - hit_point).norm_squared();
+        self.intensity * self.falloff(hit_point) / (four * T::pi() * r2)
+    }
+
+    fn distance(&self, hit_point: &na::Point3<T>) -> T {
+        (self.position - hit_point).magnitude()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+
+    use super::*;
+
+    #[test]
+    fn area_lights_sample_their_surface() {
+        let hit_point: na::Point3<f64> = na::Point3::origin();
+        let sphere = SphericalLight {
+            position: na::Point3::new(0.0, 4.0, 0.0),
+            color: na::Vector3::new(1.0, 1.0, 1.0),
+            intensity: 100.0,
+            radius: 1.0,
+            samples: 8,
+        };
+        let rectangle = RectangularLight {
+            position: na::Point3::new(0.0, 4.0, 0.0),
+            u: na::Vector3::new(2.0, 0.0, 0.0),
+            v: na::Vector3::new(0.0, 0.0, 2.0),
+            color: na::Vector3::new(1.0, 1.0, 1.0),
+            intensity: 100.0,
+            samples: 8,
+        };
+
+        for &(x, y) in &[(0.0, 0.0), (1.0, 1.0), (0.25, 0.75), (0.5, 0.5)] {
+            let sample = na::Vector2::new(x, y);
+
+            let s = sphere.sample(&hit_point, &sample);
+            let point = hit_point + s.direction * s.distance;
+            assert!((point - sphere.position).norm() <= 1.0 + 1e-12);
+
+            let s = rectangle.sample(&hit_point, &sample);
+            let point = hit_point + s.direction * s.distance;
+            assert!((point.y - 4.0).abs() < 1e-12);
+            assert!(point.x.abs() <= 1.0 + 1e-12 && point.z.abs() <= 1.0 + 1e-12);
+            assert!(s.intensity > 0.0);
+        }
+
+        // u x v points down, so nothing is emitted upwards
+        let above = na::Point3::new(0.0, 8.0, 0.0);
+        let s = rectangle.sample(&above, &na::Vector2::new(0.5, 0.5));
+        assert_eq!(s.intensity, 0.0);
+    }
+
+    #[test]
+    fn spot_light_falls_off_between_cones() {
+        let spot = SpotLight {
+            position: na::Point3::new(0.0, 1.0, 0.0),
+            direction: na::Vector3::new(0.0, -1.0, 0.0),
+            color: na::Vector3::new(1.0, 1.0, 1.0),
+            intensity: 100.0,
+            inner_angle: 20.0,
+            outer_angle: 40.0,
+        };
+
+        let at_angle = |degrees: f64| {
+            let offset = degrees.to_radians().tan();
+            spot.falloff(&na::Point3::new(offset, 0.0, 0.0))
+        };
+        assert_eq!(at_angle(0.0), 1.0);
+        assert_eq!(at_angle(19.0), 1.0);
+        assert_eq!(at_angle(41.0), 0.0);
+        assert_eq!(spot.intensity(&na::Point3::new(0.0, 2.0, 0.0)), 0.0);
+
+        let partial = at_angle(30.0);
+        assert!(partial > 0.0 && partial < 1.0);
+        assert!(at_angle(25.0) > partial && partial > at_angle(35.0));
+    }
+}
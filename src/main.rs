@@ -0,0 +1,221 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use image::Rgb32FImage;
+use rustracer::{
+    color_convert::{Dither, Primaries},
+    develop,
+    output::{self, sample_heatmap},
+    progress::Progress,
+    render_in_background,
+    scene_file::{load_scene, SceneFileError},
+    tone_mapping::ToneMapping,
+    AdaptiveSampling, RenderMode, RenderOptions,
+};
+
+/// Renders a JSON, RON or TOML scene file to an image
+#[derive(Debug, Parser)]
+#[command(name = "rustracer", version, about)]
+struct Args {
+    /// Scene description to render
+    scene: PathBuf,
+    /// Where to write the image, .exr and .pfm keep the linear HDR values
+    #[arg(short, long, default_value = "render.png")]
+    output: PathBuf,
+    /// Image width, overriding the scene
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+    /// Image height, overriding the scene
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+    /// Samples per pixel
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: Option<u32>,
+    /// Worker threads, all cores by default
+    #[arg(short, long)]
+    threads: Option<usize>,
+    /// Width and height in pixels of the blocks handed to each thread
+    #[arg(long)]
+    tile_size: Option<u32>,
+    /// Maximum number of bounces, overriding the scene
+    #[arg(long)]
+    max_depth: Option<u32>,
+    /// Seed for the random numbers, overriding the scene. The same scene and seed always give
+    /// the same image
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Stop after this many seconds and write whatever tiles are finished
+    #[arg(long, value_parser = parse_seconds)]
+    time_limit: Option<Duration>,
+    /// Refine the whole image one sample per pixel at a time, rewriting the output after
+    /// every pass. With a time limit the last pass in progress is finished
+    #[arg(long)]
+    progressive: bool,
+    /// Keep sampling pixels until the standard error of their mean, relative to the mean, is
+    /// below this
+    #[arg(long)]
+    adaptive: Option<f64>,
+    /// Most samples any pixel takes with --adaptive, four times the sample count by default
+    #[arg(long, requires = "adaptive")]
+    max_samples: Option<u32>,
+    /// Also write an image of how many samples each pixel took
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+}
+
+// a positive, finite number of seconds
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds = value.parse::<f64>().map_err(|e| e.to_string())?;
+    if seconds <= 0.0 || !seconds.is_finite() {
+        return Err(format!(
+            "{} is not a positive, finite number of seconds",
+            value
+        ));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+// writes hdr formats as they are and develops everything else to 8 bits
+fn save(
+    image: &Rgb32FImage,
+    path: &Path,
+    settings: (ToneMapping, Primaries, Dither),
+) -> Result<(), String> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let result = match extension.as_deref() {
+        Some("exr") => output::write_exr(image, path).map_err(|e| e.to_string()),
+        Some("pfm") => output::write_pfm(image, path).map_err(|e| e.to_string()),
+        _ => {
+            let (tone_mapping, primaries, dither) = settings;
+            let (image, report) = develop(image, tone_mapping, primaries, dither);
+            if report.invalid_pixels > 0 {
+                eprintln!(
+                    "\nwarning: {} pixels had invalid values and were written as black",
+                    report.invalid_pixels
+                );
+            }
+            image.save(path).map_err(|e| e.to_string())
+        }
+    };
+    result.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1)
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let start = Instant::now();
+    let loaded = load_scene::<f64, _>(&args.scene).unwrap_or_else(|e| match e {
+        SceneFileError::Parse { .. } | SceneFileError::Invalid { .. } => {
+            fail(format!("{}: {}", args.scene.display(), e))
+        }
+        _ => fail(e.to_string()),
+    });
+    for warning in &loaded.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    let mut scene = loaded.scene;
+    scene.width = args.width.unwrap_or(scene.width);
+    scene.height = args.height.unwrap_or(scene.height);
+    scene.samples = args.samples.unwrap_or(scene.samples);
+    scene.max_recursion_depth = args.max_depth.unwrap_or(scene.max_recursion_depth);
+    scene.seed = args.seed.unwrap_or(scene.seed);
+    println!("loaded {} in {:.2?}", args.scene.display(), start.elapsed());
+
+    let mut options = RenderOptions::default();
+    if let Some(threads) = args.threads {
+        options.threads = threads;
+    }
+    if let Some(tile_size) = args.tile_size {
+        options.tile_size = tile_size;
+    }
+    options.adaptive = args.adaptive.map(|threshold| AdaptiveSampling {
+        threshold,
+        max_samples: args.max_samples.unwrap_or(scene.samples * 4),
+    });
+    options.progress = Some(Arc::new(|progress: &Progress| {
+        let remaining = match progress.remaining() {
+            Some(remaining) => format!("{:.1?} remaining", remaining),
+            None => "estimating".to_string(),
+        };
+        print!(
+            "\rrendering {:3.0}%, pass {}/{}, {:.1?} elapsed, {:<24}",
+            progress.fraction() * 100.0,
+            (progress.completed_passes + 1).min(progress.total_passes),
+            progress.total_passes,
+            progress.elapsed,
+            remaining
+        );
+        io::stdout().flush().ok();
+    }));
+
+    println!(
+        "rendering {}x{} at {} samples per pixel on {} threads",
+        scene.width, scene.height, scene.samples, options.threads
+    );
+    let settings = (scene.tone_mapping, scene.primaries, scene.dither);
+    let time_limit = args.time_limit;
+    // the preview after the last pass is already the final image, so it only needs writing again
+    // if that preview couldn't be written
+    let preview_written = Arc::new(AtomicBool::new(false));
+    if args.progressive {
+        options.mode = RenderMode::Progressive {
+            time_budget: time_limit,
+        };
+        let output = args.output.clone();
+        let written = preview_written.clone();
+        options.preview = Some(Arc::new(move |image: &Rgb32FImage, _samples| {
+            // a failed preview shouldn't stop the render, which can still be written at the end
+            let result = save(image, &output, settings);
+            if let Err(e) = &result {
+                eprintln!("\nwarning: failed to write the preview, {}", e);
+            }
+            written.store(result.is_ok(), Ordering::Relaxed);
+        }));
+    }
+
+    let start = Instant::now();
+    let handle = render_in_background(scene, options);
+    if let (Some(limit), false) = (time_limit, args.progressive) {
+        while !handle.is_finished() {
+            if start.elapsed() > limit {
+                handle.cancel();
+                eprint!("\ntime limit reached, writing the finished tiles");
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+    let (image, counts) = handle
+        .join_with_sample_counts()
+        .unwrap_or_else(|e| fail(e.to_string()));
+    println!("\nrendered in {:.2?}", start.elapsed());
+
+    if !preview_written.load(Ordering::Relaxed) {
+        save(&image, &args.output, settings).unwrap_or_else(|e| fail(e));
+    }
+    println!("wrote {}", args.output.display());
+    if let Some(path) = &args.heatmap {
+        sample_heatmap(&counts)
+            .save(path)
+            .unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)));
+        println!("wrote {}", path.display());
+    }
+}
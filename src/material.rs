@@ -21,4 +21,19 @@ where
     pub color: Box<dyn Coloration<T>>,
     pub surface: SurfaceType<T>,
     pub albedo: T,
+    pub emission: na::Vector3<T>,
+    pub emission_strength: T,
+}
+
+impl<T> Material<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub fn emitted(&self) -> na::Vector3<T> {
+        self.emission * self.emission_strength
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission_strength > T::zero() && self.emission.max() > T::zero()
+    }
 }
@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use image::DynamicImage;
+use nalgebra as na;
+use num::ToPrimitive;
+
+use crate::{
+    color_convert::ColorSpace,
+    coloration::{Color, Coloration, Texture},
+    material::SurfaceType,
+    objects::{Intersectable, TriangleMesh},
+    Material,
+};
+
+const DEFAULT_DIFFUSE: [f32; 3] = [0.8, 0.8, 0.8];
+const DEFAULT_ALBEDO: f64 = 0.18;
+const MAX_SHININESS: f32 = 1000.0;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Load(tobj::LoadError),
+    Texture {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Load(e) => write!(f, "failed to load obj: {}", e),
+            ObjError::Texture { path, source } => {
+                write!(f, "failed to load texture {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Load(e) => Some(e),
+            ObjError::Texture { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<tobj::LoadError> for ObjError {
+    fn from(e: tobj::LoadError) -> Self {
+        ObjError::Load(e)
+    }
+}
+
+fn convert<T>(value: f32) -> T
+where
+    T: na::RealField + ToPrimitive,
+{
+    na::convert(f64::from(value))
+}
+
+fn convert_surface<T>(material: &tobj::Material) -> SurfaceType<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let dissolve = material.dissolve.unwrap_or(1.0);
+    if dissolve < 1.0 {
+        return SurfaceType::Refractive {
+            index: convert(material.optical_density.unwrap_or(1.0)),
+            transparency: convert(1.0 - dissolve),
+        };
+    }
+
+    let shininess = material.shininess.unwrap_or(0.0).clamp(0.0, MAX_SHININESS) / MAX_SHININESS;
+    let specular = material
+        .specular
+        .map_or(0.0, |[r, g, b]| r.max(g).max(b).clamp(0.0, 1.0));
+    let reflectivity = shininess * specular;
+    if reflectivity > 0.0 {
+        SurfaceType::Reflective {
+            reflectivity: convert(reflectivity),
+        }
+    } else {
+        SurfaceType::Diffuse
+    }
+}
+
+fn convert_material<T>(
+    material: Option<&tobj::Material>,
+    base_dir: &Path,
+    textures: &mut HashMap<PathBuf, DynamicImage>,
+) -> Result<Material<T>, ObjError>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let material = match material {
+        Some(material) => material,
+        None => {
+            return Ok(Material {
+                color: Box::new(Color {
+                    color: DEFAULT_DIFFUSE.map(convert).into(),
+                }),
+                surface: SurfaceType::Diffuse,
+                albedo: na::convert(DEFAULT_ALBEDO),
+                emission: na::Vector3::zeros(),
+                emission_strength: T::zero(),
+            })
+        }
+    };
+
+    let color: Box<dyn Coloration<T>> = match &material.diffuse_texture {
+        Some(texture) => {
+            let path = absolute(&base_dir.join(texture));
+            let texture = match textures.get(&path) {
+                Some(texture) => texture.clone(),
+                None => {
+                    let texture = image::open(&path).map_err(|source| ObjError::Texture {
+                        path: path.clone(),
+                        source,
+                    })?;
+                    textures.insert(path.clone(), texture.clone());
+                    texture
+                }
+            };
+            Box::new(Texture {
+                texture,
+                color_space: ColorSpace::Srgb,
+                path: Some(path),
+            })
+        }
+        None => Box::new(Color {
+            color: material
+                .diffuse
+                .unwrap_or(DEFAULT_DIFFUSE)
+                .map(convert)
+                .into(),
+        }),
+    };
+
+    // Ke is the emitted radiance itself, so it needs no separate strength
+    let emission = material.emissive.unwrap_or([0.0; 3]);
+    let emission_strength = if emission.iter().any(|&e| e > 0.0) {
+        T::one()
+    } else {
+        T::zero()
+    };
+
+    Ok(Material {
+        color,
+        surface: convert_surface(material),
+        albedo: na::convert(DEFAULT_ALBEDO),
+        emission: emission.map(convert).into(),
+        emission_strength,
+    })
+}
+
+// so the paths a loaded scene remembers don't depend on the working directory
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.into())
+}
+
+fn convert_mesh<T>(mesh: tobj::Mesh, material: Material<T>) -> TriangleMesh<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let positions = mesh
+        .positions
+        .chunks_exact(3)
+        .map(|p| na::Point3::new(convert(p[0]), convert(p[1]), convert(p[2])))
+        .collect::<Vec<_>>();
+
+    let mut normals = mesh
+        .normals
+        .chunks_exact(3)
+        .map(|n| na::Vector3::new(convert(n[0]), convert(n[1]), convert(n[2])))
+        .collect::<Vec<_>>();
+    if normals.len() != positions.len() {
+        normals.clear();
+    }
+
+    // obj texture coordinates start at the bottom of the image
+    let mut tex_coords = mesh
+        .texcoords
+        .chunks_exact(2)
+        .map(|t| na::Vector2::new(convert(t[0]), convert(1.0 - t[1])))
+        .collect::<Vec<_>>();
+    if tex_coords.len() != positions.len() {
+        tex_coords.clear();
+    }
+
+    let indices = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|f| [f[0] as usize, f[1] as usize, f[2] as usize])
+        .collect();
+
+    TriangleMesh::new(positions, normals, tex_coords, indices, material)
+}
+
+#[derive(Debug)]
+pub struct LoadedObj<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub objects: Vec<Box<dyn Intersectable<T>>>,
+    // problems that didn't stop the load, for the caller to report
+    pub warnings: Vec<String>,
+}
+
+pub fn load_obj<T, P>(path: P) -> Result<LoadedObj<T>, ObjError>
+where
+    T: na::RealField + ToPrimitive,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+    // like other importers, geometry still loads when its material library can't be read
+    let mut warnings = Vec::new();
+    let materials = materials.unwrap_or_else(|e| {
+        warnings.push(format!(
+            "using default materials for {}: {}",
+            path.display(),
+            e
+        ));
+        Vec::new()
+    });
+
+    let mut textures = HashMap::new();
+    let mut objects: Vec<Box<dyn Intersectable<T>>> = Vec::with_capacity(models.len());
+    for model in models {
+        let material = model.mesh.material_id.and_then(|id| materials.get(id));
+        let material = convert_material(material, base_dir, &mut textures)?;
+        let mesh = convert_mesh(model.mesh, material).with_source(absolute(path));
+        objects.push(Box::new(mesh));
+    }
+    Ok(LoadedObj { objects, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use nalgebra as na;
+
+    use super::*;
+    use crate::ray::Ray;
+
+    #[test]
+    fn loads_meshes_and_materials() {
+        let dir = std::env::temp_dir().join("rustracer_obj_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("quad.mtl"),
+            "newmtl glass\nKd 1.0 1.0 1.0\nd 0.25\nNi 1.5\nKe 2.0 1.0 0.5\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("quad.obj"),
+            "mtllib quad.mtl\n\
+             v -1 -1 -3\nv 1 -1 -3\nv 1 1 -3\nv -1 1 -3\n\
+             usemtl glass\nf 1 2 3 4\n",
+        )
+        .unwrap();
+
+        let loaded = load_obj::<f64, _>(dir.join("quad.obj")).unwrap();
+        assert!(loaded.warnings.is_empty());
+        let objects = loaded.objects;
+        assert_eq!(objects.len(), 1);
+
+        let quad = &objects[0];
+        assert_eq!(
+            quad.material().surface,
+            SurfaceType::Refractive {
+                index: 1.5,
+                transparency: 0.75
+            }
+        );
+        assert_eq!(quad.material().emitted(), na::Vector3::new(2.0, 1.0, 0.5));
+
+        let ray = Ray {
+            origin: na::Point3::origin(),
+            direction: na::Vector3::new(0.1, 0.1, -1.0).normalize(),
+        };
+        assert!(quad.intersect(&ray).is_some());
+    }
+
+    #[test]
+    fn missing_material_library_falls_back_to_default() {
+        let dir = std::env::temp_dir().join("rustracer_obj_missing_mtl_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("tri.obj"),
+            "mtllib missing.mtl\n\
+             v -1 -1 -3\nv 1 -1 -3\nv 0 1 -3\n\
+             usemtl gone\nf 1 2 3\n",
+        )
+        .unwrap();
+
+        let loaded = load_obj::<f64, _>(dir.join("tri.obj")).unwrap();
+        assert_eq!(loaded.objects.len(), 1);
+        assert_eq!(loaded.objects[0].material().surface, SurfaceType::Diffuse);
+        assert_eq!(loaded.warnings.len(), 1);
+        assert!(loaded.warnings[0].contains("tri.obj"));
+    }
+}
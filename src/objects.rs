@@ -1,11 +1,32 @@
-use std::fmt::Debug;
+use std::{
+    any::Any,
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
 
 use nalgebra as na;
 use num::ToPrimitive;
 
-use crate::{Material, ray::Ray};
+use crate::{
+    bvh::{Aabb, Bvh},
+    ray::Ray,
+    sampling::{uniform_sphere, uniform_triangle},
+    Material,
+};
 
-pub trait Intersectable<T>: Debug + Sync + Send
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub point: na::Point3<T>,
+    pub normal: na::Vector3<T>,
+    // probability density with respect to surface area
+    pub pdf: T,
+}
+
+// Any lets a scene file find out what kind of object it is writing out
+pub trait Intersectable<T>: Any + Debug + Sync + Send
 where
     T: na::RealField + ToPrimitive,
 {
@@ -13,8 +34,20 @@ where
     fn surface_normal(&self, hit_point: &na::Point3<T>) -> na::Vector3<T>;
     fn texture_coords(&self, hit_point: &na::Point3<T>) -> na::Vector2<T>;
     fn material(&self) -> &Material<T>;
-}
+    // None for unbounded objects, which are kept outside the scene's bvh
+    fn bounding_box(&self) -> Option<Aabb<T>>;
+
+    // whether sample_surface can pick points on the object, so an emissive one can be treated
+    // as a light. objects that can't be sampled only emit light when hit directly
+    fn can_sample_surface(&self) -> bool {
+        false
+    }
 
+    // a point on the surface chosen by a sample in the unit square
+    fn sample_surface(&self, _sample: &na::Vector2<T>) -> Option<SurfaceSample<T>> {
+        None
+    }
+}
 
 #[derive(Debug)]
 pub struct Sphere<T>
@@ -44,7 +77,7 @@ where
         let t1 = adj + thc;
 
         if t0 < T::zero() && t1 < T::zero() {
-            return None;
+            None
         } else if t0 < T::zero() {
             Some(t1)
         } else if t1 < T::zero() {
@@ -69,6 +102,25 @@ where
     fn material(&self) -> &Material<T> {
         &self.material
     }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        let radius = na::Vector3::repeat(self.radius);
+        Some(Aabb::new(self.center - radius, self.center + radius))
+    }
+
+    fn can_sample_surface(&self) -> bool {
+        true
+    }
+
+    fn sample_surface(&self, sample: &na::Vector2<T>) -> Option<SurfaceSample<T>> {
+        let normal = uniform_sphere(sample);
+        let four = T::from_f64(4.0).unwrap();
+        Some(SurfaceSample {
+            point: self.center + normal * self.radius,
+            normal,
+            pdf: T::one() / (four * T::pi() * self.radius * self.radius),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -90,7 +142,7 @@ where
         let denom = normal.dot(&ray.direction);
         if denom > na::convert(1e-6) {
             let v = self.origin - ray.origin;
-            let distance = v.dot(&normal) / denom;
+            let distance = v.dot(normal) / denom;
             if distance >= T::zero() {
                 return Some(distance);
             }
@@ -121,5 +173,453 @@ where
     fn material(&self) -> &Material<T> {
         &self.material
     }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        None
+    }
+}
+
+fn intersect_triangle<T>(vertices: [&na::Point3<T>; 3], ray: &Ray<T>) -> Option<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let [v0, v1, v2] = vertices;
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let p = ray.direction.cross(&edge2);
+    let det = edge1.dot(&p);
+    if det.abs() < na::convert(1e-12) {
+        return None;
+    }
+
+    let inv_det = T::one() / det;
+    let s = ray.origin - v0;
+    let u = s.dot(&p) * inv_det;
+    if u < T::zero() || u > T::one() {
+        return None;
+    }
+
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inv_det;
+    if v < T::zero() || u + v > T::one() {
+        return None;
+    }
+
+    let distance = edge2.dot(&q) * inv_det;
+    if distance >= T::zero() {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+const LOCATE_TOLERANCE: f64 = 1e-6;
+
+// weights of each vertex for a point on (or near) the triangle's plane
+fn barycentric<T>(vertices: [&na::Point3<T>; 3], point: &na::Point3<T>) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let [v0, v1, v2] = vertices;
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let to_point = point - v0;
+    let d11 = edge1.dot(&edge1);
+    let d12 = edge1.dot(&edge2);
+    let d22 = edge2.dot(&edge2);
+    let dp1 = to_point.dot(&edge1);
+    let dp2 = to_point.dot(&edge2);
+    let denom = d11 * d22 - d12 * d12;
+    let u = (d22 * dp1 - d12 * dp2) / denom;
+    let v = (d11 * dp2 - d12 * dp1) / denom;
+    na::Vector3::new(T::one() - u - v, u, v)
+}
+
+fn face_normal<T>(vertices: [&na::Point3<T>; 3]) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let [v0, v1, v2] = vertices;
+    (v1 - v0).cross(&(v2 - v0)).normalize()
+}
+
+fn triangle_area<T>(vertices: [&na::Point3<T>; 3]) -> T
+where
+    T: na::RealField + ToPrimitive,
+{
+    let [v0, v1, v2] = vertices;
+    (v1 - v0).cross(&(v2 - v0)).norm() / na::convert(2.0)
+}
+
+#[derive(Debug)]
+pub struct Triangle<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub vertices: [na::Point3<T>; 3],
+    pub normals: Option<[na::Vector3<T>; 3]>,
+    pub tex_coords: Option<[na::Vector2<T>; 3]>,
+    pub material: Material<T>,
+}
+
+impl<T> Triangle<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn vertex_refs(&self) -> [&na::Point3<T>; 3] {
+        let [v0, v1, v2] = &self.vertices;
+        [v0, v1, v2]
+    }
 }
 
+impl<T> Intersectable<T> for Triangle<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn intersect(&self, ray: &Ray<T>) -> Option<T> {
+        intersect_triangle(self.vertex_refs(), ray)
+    }
+
+    fn surface_normal(&self, hit_point: &na::Point3<T>) -> na::Vector3<T> {
+        match &self.normals {
+            Some([n0, n1, n2]) => {
+                let bary = barycentric(self.vertex_refs(), hit_point);
+                (n0 * bary.x + n1 * bary.y + n2 * bary.z).normalize()
+            }
+            None => face_normal(self.vertex_refs()),
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &na::Point3<T>) -> na::Vector2<T> {
+        let bary = barycentric(self.vertex_refs(), hit_point);
+        match &self.tex_coords {
+            Some([t0, t1, t2]) => t0 * bary.x + t1 * bary.y + t2 * bary.z,
+            None => na::Vector2::new(bary.y, bary.z),
+        }
+    }
+
+    fn material(&self) -> &Material<T> {
+        &self.material
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        Some(Aabb::from_points(&self.vertices))
+    }
+
+    fn can_sample_surface(&self) -> bool {
+        true
+    }
+
+    fn sample_surface(&self, sample: &na::Vector2<T>) -> Option<SurfaceSample<T>> {
+        let [v0, v1, v2] = self.vertex_refs();
+        let bary = uniform_triangle(sample);
+        let point = na::Point3::from(v0.coords * bary.x + v1.coords * bary.y + v2.coords * bary.z);
+        let area = triangle_area(self.vertex_refs());
+        Some(SurfaceSample {
+            point,
+            normal: self.surface_normal(&point),
+            pdf: T::one() / area,
+        })
+    }
+}
+
+// the geometry is fixed once the mesh is built, since its faces are indexed by a bvh
+#[derive(Debug)]
+pub struct TriangleMesh<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    positions: Vec<na::Point3<T>>,
+    normals: Vec<na::Vector3<T>>,
+    tex_coords: Vec<na::Vector2<T>>,
+    indices: Vec<[usize; 3]>,
+    pub material: Material<T>,
+    bvh: Bvh<T>,
+    // running total of the face areas, for picking faces in proportion to their size
+    area_cdf: Vec<T>,
+    // the obj file the mesh came from, which a scene file refers to instead of the triangles
+    source: Option<PathBuf>,
+}
+
+impl<T> TriangleMesh<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    // normals and tex_coords are either empty or indexed the same as positions
+    pub fn new(
+        positions: Vec<na::Point3<T>>,
+        normals: Vec<na::Vector3<T>>,
+        tex_coords: Vec<na::Vector2<T>>,
+        indices: Vec<[usize; 3]>,
+        material: Material<T>,
+    ) -> TriangleMesh<T> {
+        let bounds = indices
+            .iter()
+            .map(|&[i0, i1, i2]| {
+                let vertices = [&positions[i0], &positions[i1], &positions[i2]];
+                Some(Aabb::from_points(vertices.iter().copied()))
+            })
+            .collect::<Vec<_>>();
+        let area_cdf = indices
+            .iter()
+            .scan(T::zero(), |total, &[i0, i1, i2]| {
+                *total += triangle_area([&positions[i0], &positions[i1], &positions[i2]]);
+                Some(*total)
+            })
+            .collect();
+        TriangleMesh {
+            positions,
+            normals,
+            tex_coords,
+            indices,
+            material,
+            bvh: Bvh::build(&bounds),
+            area_cdf,
+            source: None,
+        }
+    }
+
+    pub fn with_source(mut self, path: PathBuf) -> TriangleMesh<T> {
+        self.source = Some(path);
+        self
+    }
+
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    pub fn positions(&self) -> &[na::Point3<T>] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[na::Vector3<T>] {
+        &self.normals
+    }
+
+    pub fn tex_coords(&self) -> &[na::Vector2<T>] {
+        &self.tex_coords
+    }
+
+    pub fn indices(&self) -> &[[usize; 3]] {
+        &self.indices
+    }
+
+    fn face_vertices(&self, face: usize) -> [&na::Point3<T>; 3] {
+        let [i0, i1, i2] = self.indices[face];
+        [
+            &self.positions[i0],
+            &self.positions[i1],
+            &self.positions[i2],
+        ]
+    }
+
+    // distance of a point from a face's plane, if it lies within the face
+    fn face_distance(&self, face: usize, point: &na::Point3<T>) -> Option<(T, na::Vector3<T>)> {
+        let vertices = self.face_vertices(face);
+        let bary = barycentric(vertices, point);
+        if bary.min() < -na::convert::<f64, T>(LOCATE_TOLERANCE) {
+            return None;
+        }
+
+        let distance = face_normal(vertices).dot(&(point - vertices[0])).abs();
+        Some((distance, bary))
+    }
+
+    // finds the face a previously intersected point lies on, along with its barycentric coordinates
+    fn locate(&self, hit_point: &na::Point3<T>) -> (usize, na::Vector3<T>) {
+        let face = self
+            .bvh
+            .locate(hit_point, na::convert(LOCATE_TOLERANCE), |face| {
+                self.face_distance(face, hit_point).map(|(d, _)| d)
+            })
+            .unwrap_or(0);
+
+        let bary = barycentric(self.face_vertices(face), hit_point);
+        (face, bary)
+    }
+
+    fn face_surface_normal(&self, face: usize, bary: &na::Vector3<T>) -> na::Vector3<T> {
+        if self.normals.is_empty() {
+            return face_normal(self.face_vertices(face));
+        }
+
+        let [i0, i1, i2] = self.indices[face];
+        (self.normals[i0] * bary.x + self.normals[i1] * bary.y + self.normals[i2] * bary.z)
+            .normalize()
+    }
+}
+
+impl<T> Intersectable<T> for TriangleMesh<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn intersect(&self, ray: &Ray<T>) -> Option<T> {
+        self.bvh
+            .intersect(ray, |face| {
+                intersect_triangle(self.face_vertices(face), ray)
+            })
+            .map(|(_, distance)| distance)
+    }
+
+    fn surface_normal(&self, hit_point: &na::Point3<T>) -> na::Vector3<T> {
+        let (face, bary) = self.locate(hit_point);
+        self.face_surface_normal(face, &bary)
+    }
+
+    fn texture_coords(&self, hit_point: &na::Point3<T>) -> na::Vector2<T> {
+        let (face, bary) = self.locate(hit_point);
+        if self.tex_coords.is_empty() {
+            return na::Vector2::new(bary.y, bary.z);
+        }
+
+        let [i0, i1, i2] = self.indices[face];
+        self.tex_coords[i0] * bary.x + self.tex_coords[i1] * bary.y + self.tex_coords[i2] * bary.z
+    }
+
+    fn material(&self) -> &Material<T> {
+        &self.material
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        Some(Aabb::from_points(&self.positions))
+    }
+
+    fn can_sample_surface(&self) -> bool {
+        true
+    }
+
+    // picks a face with probability proportional to its area and then a point on it, so points
+    // are uniform over the whole mesh
+    fn sample_surface(&self, sample: &na::Vector2<T>) -> Option<SurfaceSample<T>> {
+        let total = *self.area_cdf.last()?;
+        if total <= T::zero() {
+            return None;
+        }
+
+        // faces with no area are never picked, since their running total equals the one before
+        let target = sample.x * total;
+        let face = match self.area_cdf.partition_point(|&cdf| cdf <= target) {
+            face if face < self.area_cdf.len() => face,
+            _ => self.area_cdf.partition_point(|&cdf| cdf < total),
+        };
+        // reuse where the sample fell within the face's share to pick the point on it
+        let start = if face == 0 {
+            T::zero()
+        } else {
+            self.area_cdf[face - 1]
+        };
+        let area = self.area_cdf[face] - start;
+        let remapped = na::Vector2::new(((target - start) / area).min(T::one()), sample.y);
+
+        let [v0, v1, v2] = self.face_vertices(face);
+        let bary = uniform_triangle(&remapped);
+        Some(SurfaceSample {
+            point: na::Point3::from(v0.coords * bary.x + v1.coords * bary.y + v2.coords * bary.z),
+            normal: self.face_surface_normal(face, &bary),
+            pdf: T::one() / total,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+
+    use super::*;
+    use crate::{coloration::Color, material::SurfaceType};
+
+    fn material() -> Material<f64> {
+        Material {
+            color: Box::new(Color {
+                color: na::Vector3::new(1.0, 1.0, 1.0),
+            }),
+            surface: SurfaceType::Diffuse,
+            albedo: 0.18,
+            emission: na::Vector3::zeros(),
+            emission_strength: 0.0,
+        }
+    }
+
+    #[test]
+    fn mesh_interpolates_vertex_attributes() {
+        let mesh = TriangleMesh::new(
+            vec![
+                na::Point3::new(-1.0, -1.0, -2.0),
+                na::Point3::new(1.0, -1.0, -2.0),
+                na::Point3::new(1.0, 1.0, -2.0),
+                na::Point3::new(-1.0, 1.0, -2.0),
+            ],
+            vec![
+                na::Vector3::new(-1.0, 0.0, 1.0).normalize(),
+                na::Vector3::new(1.0, 0.0, 1.0).normalize(),
+                na::Vector3::new(1.0, 0.0, 1.0).normalize(),
+                na::Vector3::new(-1.0, 0.0, 1.0).normalize(),
+            ],
+            vec![
+                na::Vector2::new(0.0, 0.0),
+                na::Vector2::new(1.0, 0.0),
+                na::Vector2::new(1.0, 1.0),
+                na::Vector2::new(0.0, 1.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            material(),
+        );
+
+        let ray = Ray {
+            origin: na::Point3::new(0.5, 0.5, 0.0),
+            direction: na::Vector3::new(0.0, 0.0, -1.0),
+        };
+        let distance = mesh.intersect(&ray).unwrap();
+        assert!((distance - 2.0).abs() < 1e-9);
+
+        let hit_point = ray.origin + ray.direction * distance;
+        let normal = mesh.surface_normal(&hit_point);
+        assert!(normal.x > 0.0 && normal.z > 0.0);
+        let tex_coords = mesh.texture_coords(&hit_point);
+        assert!((tex_coords - na::Vector2::new(0.75, 0.75)).norm() < 1e-9);
+
+        let miss = Ray {
+            origin: na::Point3::new(2.0, 0.0, 0.0),
+            direction: na::Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert!(mesh.intersect(&miss).is_none());
+    }
+
+    #[test]
+    fn mesh_samples_are_uniform_over_its_area() {
+        // a unit square next to a triangle with a quarter of its area, with a degenerate face
+        let mesh = TriangleMesh::new(
+            vec![
+                na::Point3::new(0.0, 0.0, 0.0),
+                na::Point3::new(1.0, 0.0, 0.0),
+                na::Point3::new(1.0, 1.0, 0.0),
+                na::Point3::new(0.0, 1.0, 0.0),
+                na::Point3::new(2.0, 0.0, 0.0),
+                na::Point3::new(2.0, 0.5, 0.0),
+            ],
+            vec![],
+            vec![],
+            vec![[0, 1, 2], [0, 2, 3], [1, 1, 2], [1, 4, 5]],
+            material(),
+        );
+        assert!(mesh.can_sample_surface());
+
+        let n = 100;
+        let mut on_triangle = 0;
+        for i in 0..n {
+            for j in 0..n {
+                let sample = na::Vector2::new(f64::from(i) + 0.5, f64::from(j) + 0.5) / 100.0;
+                let surface = mesh.sample_surface(&sample).unwrap();
+                assert!((surface.pdf - 1.0 / 1.25).abs() < 1e-12);
+                assert!((surface.normal - na::Vector3::z()).norm() < 1e-12);
+                assert!(surface.point.z.abs() < 1e-12);
+                assert!((0.0..=2.0).contains(&surface.point.x));
+                assert!((0.0..=1.0).contains(&surface.point.y));
+                if surface.point.x > 1.0 {
+                    on_triangle += 1;
+                }
+            }
+        }
+        assert_eq!(on_triangle, n * n / 5);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use image::{DynamicImage, ImageBuffer, ImageResult, Luma, Rgb, Rgb32FImage, RgbImage};
+
+// how many samples each pixel took, which varies with adaptive sampling
+pub type SampleCounts = ImageBuffer<Luma<u32>, Vec<u32>>;
+
+pub fn write_exr<P: AsRef<Path>>(image: &Rgb32FImage, path: P) -> ImageResult<()> {
+    DynamicImage::ImageRgb32F(image.clone()).save_with_format(path, image::ImageFormat::OpenExr)
+}
+
+// portable float map, stored little endian with the bottom row first
+pub fn write_pfm<P: AsRef<Path>>(image: &Rgb32FImage, path: P) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+    for row in image.rows().rev() {
+        for pixel in row {
+            for channel in pixel.0.iter() {
+                file.write_all(&channel.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+// false colors from black through red and yellow to white for the fewest to the most samples
+pub fn sample_heatmap(counts: &SampleCounts) -> RgbImage {
+    const STOPS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [0.8, 0.0, 0.0],
+        [1.0, 0.9, 0.0],
+        [1.0, 1.0, 1.0],
+    ];
+    let (min, max) = counts.pixels().fold((u32::MAX, 0), |(min, max), p| {
+        (min.min(p.0[0]), max.max(p.0[0]))
+    });
+    RgbImage::from_fn(counts.width(), counts.height(), |x, y| {
+        let count = counts.get_pixel(x, y).0[0];
+        let t = if max > min {
+            (count - min) as f32 / (max - min) as f32
+        } else {
+            0.0
+        };
+        let position = t * (STOPS.len() - 1) as f32;
+        let i = (position as usize).min(STOPS.len() - 2);
+        let f = position - i as f32;
+        let color = |c: usize| STOPS[i][c] + (STOPS[i + 1][c] - STOPS[i][c]) * f;
+        Rgb([0, 1, 2].map(|c| (color(c) * 255.0).round() as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryInto, fs};
+
+    use image::Rgb;
+
+    use super::*;
+
+    #[test]
+    fn writes_hdr_values_losslessly() {
+        let mut image = Rgb32FImage::new(3, 2);
+        image.put_pixel(0, 0, Rgb([12.5, 0.25, 0.0]));
+        image.put_pixel(2, 1, Rgb([0.0, 1e-3, 1000.0]));
+
+        let dir = std::env::temp_dir().join("rustracer_output_test");
+        fs::create_dir_all(&dir).unwrap();
+
+        write_pfm(&image, dir.join("image.pfm")).unwrap();
+        let pfm = fs::read(dir.join("image.pfm")).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&pfm[..header.len()], header);
+        let data = &pfm[header.len()..];
+        assert_eq!(data.len(), 3 * 2 * 3 * 4);
+        // the top left pixel is at the start of the last row in the file
+        let top_left = &data[3 * 3 * 4..3 * 3 * 4 + 4];
+        assert_eq!(f32::from_le_bytes(top_left.try_into().unwrap()), 12.5);
+
+        write_exr(&image, dir.join("image.exr")).unwrap();
+        let exr = image::open(dir.join("image.exr")).unwrap().into_rgb32f();
+        assert_eq!(exr, image);
+    }
+}
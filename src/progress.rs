@@ -0,0 +1,70 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use image::Rgb32FImage;
+
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+// receives the image so far and the number of samples per pixel in it
+pub type PreviewCallback = Arc<dyn Fn(&Rgb32FImage, u32) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Progress {
+    // progressive renders go over every tile once per pass
+    pub completed_passes: u32,
+    pub total_passes: u32,
+    pub completed_tiles: usize,
+    pub total_tiles: usize,
+    pub completed_pixels: u64,
+    pub total_pixels: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.total_pixels == 0 {
+            1.0
+        } else {
+            self.completed_pixels as f64 / self.total_pixels as f64
+        }
+    }
+
+    // extrapolated from the pixels finished so far, unknown until the first tile is done
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.completed_pixels == 0 {
+            return None;
+        }
+        let remaining = self.total_pixels - self.completed_pixels;
+        Some(
+            self.elapsed
+                .mul_f64(remaining as f64 / self.completed_pixels as f64),
+        )
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completed_pixels == self.total_pixels
+    }
+}
+
+// shared flag that stops a render early. workers finish the pixel they are on and skip the
+// rest, leaving unfinished tiles black
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -1,5 +1,5 @@
 use nalgebra as na;
-use num::{integer::Roots, ToPrimitive};
+use num::ToPrimitive;
 
 use crate::scene::Scene;
 
@@ -9,43 +9,49 @@ pub struct Ray<T: na::RealField + ToPrimitive> {
     pub direction: na::Vector3<T>,
 }
 
-fn to_radians<T: na::RealField + ToPrimitive>(a: T) -> T {
-    a * T::pi() / na::convert(180.0)
-}
-
 impl<T> Ray<T>
 where
     T: na::RealField + ToPrimitive,
 {
-    pub fn new_prime(x: u32, y: u32, s: u32, scene: &Scene<T>) -> Ray<T> {
+    // the ray through the given point of pixel (x, y), leaving the lens at the given sample
+    pub fn new_prime(
+        x: u32,
+        y: u32,
+        offset: &na::Vector2<T>,
+        lens_sample: &na::Vector2<T>,
+        scene: &Scene<T>,
+    ) -> Ray<T> {
         let Scene {
-            width, height, fov, ..
+            width,
+            height,
+            ref camera,
+            ..
         } = *scene;
-        assert!(
-            width > height,
-            "width must be greater than height to prevent distortion (for now)"
-        );
-
         let (width, height) = (T::from_u32(width).unwrap(), T::from_u32(height).unwrap());
         let two = na::convert(2.0);
 
-        let aspect = width / height;
-        let fov_adj = (to_radians(fov) / two).tan();
-
-        let ss = scene.samples.sqrt();
-        let sw = T::one() / T::from_u32(ss).unwrap();
-        let sw_2 = sw / two;
-        let sx = T::from_u32(s % ss).unwrap() * sw + sw_2;
-        let sy = T::from_u32(s / ss).unwrap() * sw + sw_2;
+        let (sensor_width, sensor_height) = camera.sensor_size(width / height);
 
         let x = T::from_u32(x).unwrap();
-        let sensor_x = (((x + sx) / width) * two - T::one()) * aspect * fov_adj;
+        let sensor_x = (((x + offset.x) / width) * two - T::one()) * sensor_width;
         let y = T::from_u32(y).unwrap();
-        let sensor_y = T::one() - ((y + sy) / height) * two;
+        let sensor_y = (T::one() - ((y + offset.y) / height) * two) * sensor_height;
+
+        let (right, up, backward) = camera.basis();
+        let direction = right * sensor_x + up * sensor_y - backward;
+        if camera.aperture <= T::zero() {
+            return Ray {
+                origin: camera.eye,
+                direction: direction.normalize(),
+            };
+        }
 
+        // direction has unit length along the view axis, so this lies on the plane of focus
+        let focus_point = camera.eye + direction * camera.focus_distance;
+        let origin = camera.lens_point(lens_sample);
         Ray {
-            origin: na::Point3::origin(),
-            direction: na::Vector3::new(sensor_x, sensor_y, -T::one()).normalize(),
+            origin,
+            direction: (focus_point - origin).normalize(),
         }
     }
 
@@ -92,3 +98,117 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+
+    use super::*;
+    use crate::{
+        camera::{Camera, FovAxis},
+        sampler::{next_2d, SamplerKind},
+    };
+
+    fn scene(width: u32, height: u32, fov_axis: FovAxis) -> Scene<f64> {
+        let mut scene = Scene::new(
+            width,
+            height,
+            Camera {
+                eye: na::Point3::origin(),
+                target: na::Point3::new(0.0, 0.0, -1.0),
+                up: na::Vector3::new(0.0, 1.0, 0.0),
+                fov: 60.0,
+                fov_axis,
+                aperture: 0.0,
+                focus_distance: 1.0,
+            },
+        );
+        scene.shadow_bias = 1e-13;
+        scene.max_recursion_depth = 1;
+        scene
+    }
+
+    // where the ray through a pixel center lands on the plane one unit in front of the camera
+    fn project(x: u32, y: u32, scene: &Scene<f64>) -> na::Vector2<f64> {
+        let center = na::Vector2::new(0.5, 0.5);
+        let ray = Ray::new_prime(x, y, &center, &center, scene);
+        ray.direction.xy() / -ray.direction.z
+    }
+
+    fn assert_undistorted(scene: &Scene<f64>) {
+        for &(x, y) in &[
+            (0, 0),
+            (scene.width / 2, scene.height / 2),
+            (scene.width - 2, scene.height - 2),
+        ] {
+            let origin = project(x, y, scene);
+            let step_x = (project(x + 1, y, scene) - origin).norm();
+            let step_y = (project(x, y + 1, scene) - origin).norm();
+            assert!((step_x - step_y).abs() < 1e-12, "{} != {}", step_x, step_y);
+        }
+    }
+
+    #[test]
+    fn square_and_portrait_are_undistorted() {
+        for &(width, height) in &[(64, 64), (48, 96), (96, 48)] {
+            for &fov_axis in &[FovAxis::Horizontal, FovAxis::Vertical] {
+                assert_undistorted(&scene(width, height, fov_axis));
+            }
+        }
+    }
+
+    #[test]
+    fn fov_follows_configured_axis() {
+        let (width, height) = (48, 96);
+        let half_fov = 30f64.to_radians().tan();
+
+        // pixel centers sit half a pixel in from the image edges
+        let portrait = scene(width, height, FovAxis::Horizontal);
+        let right = project(width - 1, height / 2, &portrait);
+        let expected = half_fov * f64::from(width - 1) / f64::from(width);
+        assert!((right.x - expected).abs() < 1e-12);
+
+        let portrait = scene(width, height, FovAxis::Vertical);
+        let top = project(width / 2, 0, &portrait);
+        let expected = half_fov * f64::from(height - 1) / f64::from(height);
+        assert!((top.y - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn center_ray_points_at_target() {
+        let mut scene = scene(33, 21, FovAxis::Horizontal);
+        scene.camera.eye = na::Point3::new(3.0, -2.0, 5.0);
+        scene.camera.target = na::Point3::new(-1.0, 4.0, 0.5);
+        let expected = (scene.camera.target - scene.camera.eye).normalize();
+
+        let center = na::Vector2::new(0.5, 0.5);
+        let ray = Ray::new_prime(16, 10, &center, &center, &scene);
+        assert_eq!(ray.origin, scene.camera.eye);
+        assert!((ray.direction - expected).norm() < 1e-12);
+
+        // looking straight down along up still gives usable rays
+        scene.camera.target = scene.camera.eye - na::Vector3::new(0.0, 3.0, 0.0);
+        let ray = Ray::new_prime(0, 0, &center, &center, &scene);
+        assert!(ray.direction.iter().all(|c| c.is_finite()));
+        assert!(ray.direction.y < 0.0);
+    }
+
+    #[test]
+    fn lens_rays_converge_on_focus_plane() {
+        let mut scene = scene(64, 48, FovAxis::Vertical);
+        scene.camera.aperture = 0.5;
+        scene.camera.focus_distance = 4.0;
+
+        let mut sampler = SamplerKind::Random.sampler(16, 0);
+        let offset = na::Vector2::new(0.5, 0.5);
+        let focus =
+            |ray: &Ray<f64>| ray.origin + ray.direction * ((4.0 + ray.origin.z) / -ray.direction.z);
+        let first = Ray::new_prime(10, 20, &offset, &offset, &scene);
+        for s in 0..16 {
+            sampler.start_sample(10, 20, s);
+            let ray = Ray::new_prime(10, 20, &offset, &next_2d(&mut *sampler), &scene);
+            assert!((ray.origin - scene.camera.eye).norm() <= 0.5 + 1e-12);
+            assert!((focus(&ray) - focus(&first)).norm() < 1e-9);
+        }
+    }
+}
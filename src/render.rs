@@ -1,13 +1,35 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
-use image::RgbImage;
+use image::{Rgb32FImage, RgbImage};
 use nalgebra as na;
 use num::ToPrimitive;
 use threadpool::ThreadPool;
 
 use crate::{
-    color_convert::vec3_to_rgb, intersection::Intersection, material::SurfaceType,
-    objects::Intersectable, ray::Ray, scene::Scene,
+    color_convert::{luminance, vec3_to_rgb, Dither, Primaries},
+    film::{Film, FilmTile, PixelStats},
+    intersection::Intersection,
+    lights::LightSample,
+    material::SurfaceType,
+    objects::Intersectable,
+    output::SampleCounts,
+    progress::{CancelToken, PreviewCallback, Progress, ProgressCallback},
+    ray::Ray,
+    sampler::{next_1d, next_2d, Sampler},
+    sampling::cosine_hemisphere,
+    scene::{is_sampled_emitter, Integrator, Scene},
+    tiles::{tiles, Tile, TileOrder},
+    tone_mapping::ToneMapping,
 };
 
 fn fresnel<T>(incident: na::Vector3<T>, normal: na::Vector3<T>, index: T) -> T
@@ -34,155 +56,1185 @@ where
     }
 }
 
-fn shade_diffuse<T>(
+// relative distance within which a shadow ray hitting something counts as reaching the emitter
+const EMITTER_TOLERANCE: f64 = 1e-6;
+
+fn shade_diffuse<T, S>(
     scene: &Scene<T>,
     object: &dyn Intersectable<T>,
     hit_point: na::Point3<T>,
     surface_normal: na::Vector3<T>,
+    sampler: &mut S,
 ) -> na::Vector3<T>
 where
     T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
 {
     let tex_coords = object.texture_coords(&hit_point);
 
     let mut color = na::Vector3::zeros();
     for light in &scene.lights {
-        let dir_to_light = light.direction_from(&hit_point);
+        let samples = light.shadow_samples().max(1);
+        let mut light_color = na::Vector3::zeros();
+        for _ in 0..samples {
+            let sample = next_2d(sampler);
+            let LightSample {
+                direction: dir_to_light,
+                distance,
+                intensity,
+            } = light.sample(&hit_point, &sample);
 
-        let shadow_ray = Ray {
-            origin: hit_point + (surface_normal * scene.shadow_bias),
-            direction: dir_to_light,
-        };
-        let shadow_intersection = scene.trace(&shadow_ray);
-        let in_light = shadow_intersection.is_none()
-            || shadow_intersection.unwrap().distance > light.distance(&hit_point);
-
-        let light_intensity = if in_light {
-            light.intensity(&hit_point)
-        } else {
-            T::zero()
-        };
+            let shadow_ray = Ray {
+                origin: hit_point + (surface_normal * scene.shadow_bias),
+                direction: dir_to_light,
+            };
+            let shadow_intersection = scene.trace(&shadow_ray);
+            let in_light =
+                shadow_intersection.is_none() || shadow_intersection.unwrap().distance > distance;
+
+            let light_intensity = if in_light { intensity } else { T::zero() };
 
-        let material = object.material();
+            let material = object.material();
 
-        let light_power = (surface_normal.dot(&dir_to_light)).max(T::zero()) * light_intensity;
-        let light_reflected = material.albedo / T::pi();
+            let light_power = (surface_normal.dot(&dir_to_light)).max(T::zero()) * light_intensity;
+            let light_reflected = material.albedo / T::pi();
+
+            light_color += light.color() * light_power * light_reflected;
+        }
+        light_color /= T::from_u32(samples).unwrap();
 
-        let light_color = light.color() * light_power * light_reflected;
-        color += material
+        color += object
+            .material()
             .color
             .color(&tex_coords)
             .component_mul(&light_color);
     }
+    color += shade_emitters(scene, object, hit_point, surface_normal, sampler);
+    color += shade_environment(scene, object, hit_point, surface_normal, sampler);
 
-    color.apply_into(|e| e.clamp(T::zero(), T::one()))
+    color
 }
 
-fn calculate_color<T>(
+// direct light from the environment, estimated with one importance sampled direction
+fn shade_environment<T, S>(
+    scene: &Scene<T>,
+    object: &dyn Intersectable<T>,
+    hit_point: na::Point3<T>,
+    surface_normal: na::Vector3<T>,
+    sampler: &mut S,
+) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
+{
+    let environment = match &scene.environment {
+        Some(environment) => environment,
+        None => return na::Vector3::zeros(),
+    };
+
+    let sample = next_2d(sampler);
+    let (direction, pdf) = environment.sample(&sample);
+    let cos_surface = surface_normal.dot(&direction);
+    if pdf <= T::zero() || cos_surface <= T::zero() {
+        return na::Vector3::zeros();
+    }
+
+    let shadow_ray = Ray {
+        origin: hit_point + (surface_normal * scene.shadow_bias),
+        direction,
+    };
+    if scene.trace(&shadow_ray).is_some() {
+        return na::Vector3::zeros();
+    }
+
+    let material = object.material();
+    let brdf =
+        material.color.color(&object.texture_coords(&hit_point)) * (material.albedo / T::pi());
+    environment.radiance(&direction).component_mul(&brdf) * (cos_surface / pdf)
+}
+
+// direct light from emissive objects, estimated with one sample on each of their surfaces
+fn shade_emitters<T, S>(
+    scene: &Scene<T>,
+    object: &dyn Intersectable<T>,
+    hit_point: na::Point3<T>,
+    surface_normal: na::Vector3<T>,
+    sampler: &mut S,
+) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
+{
+    let material = object.material();
+    let surface_color = material.color.color(&object.texture_coords(&hit_point));
+    let brdf = surface_color * (material.albedo / T::pi());
+
+    let mut color = na::Vector3::zeros();
+    for emitter in scene.emitters() {
+        let sample = next_2d(sampler);
+        let surface = match emitter.sample_surface(&sample) {
+            Some(surface) => surface,
+            None => continue,
+        };
+
+        let to_light = surface.point - hit_point;
+        let distance = to_light.magnitude();
+        let direction = to_light / distance;
+        let cos_surface = surface_normal.dot(&direction);
+        let cos_light = -surface.normal.dot(&direction);
+        if cos_surface <= T::zero() || cos_light <= T::zero() {
+            continue;
+        }
+
+        let shadow_ray = Ray {
+            origin: hit_point + (surface_normal * scene.shadow_bias),
+            direction,
+        };
+        let tolerance = distance * na::convert(EMITTER_TOLERANCE);
+        if let Some(occluder) = scene.trace(&shadow_ray) {
+            if occluder.distance < distance - tolerance {
+                continue;
+            }
+        }
+
+        let geometry = cos_surface * cos_light / (distance * distance * surface.pdf);
+        color += emitter.material().emitted().component_mul(&brdf) * geometry;
+    }
+    color
+}
+
+fn shade_indirect<T, S>(
+    scene: &Scene<T>,
+    object: &dyn Intersectable<T>,
+    hit_point: na::Point3<T>,
+    surface_normal: na::Vector3<T>,
+    depth: u32,
+    sampler: &mut S,
+) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
+{
+    let russian_roulette_depth = match scene.integrator {
+        Integrator::Whitted => return na::Vector3::zeros(),
+        Integrator::PathTracing {
+            russian_roulette_depth,
+        } => russian_roulette_depth,
+    };
+
+    // the cosine term and lambertian brdf cancel with the pdf of the sampled direction
+    let material = object.material();
+    let mut weight = material.color.color(&object.texture_coords(&hit_point)) * material.albedo;
+    if depth >= russian_roulette_depth {
+        let survival = weight.max().min(T::one());
+        if survival <= T::zero() || next_1d::<T, _>(sampler) >= survival {
+            return na::Vector3::zeros();
+        }
+        weight /= survival;
+    }
+
+    let sample = next_2d(sampler);
+    let bounce_ray = Ray {
+        origin: hit_point + (surface_normal * scene.shadow_bias),
+        direction: cosine_hemisphere(&surface_normal, &sample),
+    };
+    // the environment and emitters that can be sampled were already accounted for by shade_diffuse
+    trace_path(scene, &bounce_ray, depth + 1, sampler, false).component_mul(&weight)
+}
+
+fn calculate_color<T, S>(
     scene: &Scene<T>,
     ray: &Ray<T>,
     intersection: &Intersection<T>,
     depth: u32,
+    sampler: &mut S,
+    sampled_emission: bool,
 ) -> na::Vector3<T>
 where
     T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
 {
     let hit_point = ray.origin + (ray.direction * intersection.distance);
     let normal = intersection.object.surface_normal(&hit_point);
 
     let material = intersection.object.material();
-    match material.surface {
-        SurfaceType::Diffuse => shade_diffuse(scene, intersection.object, hit_point, normal),
-        SurfaceType::Reflective { reflectivity } => {
-            let mut color = shade_diffuse(scene, intersection.object, hit_point, normal);
-
-            let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit_point, scene.shadow_bias);
-
-            color *= T::one() - reflectivity;
-            color += cast_ray(scene, &reflection_ray, depth + 1) * reflectivity;
-            color
-        }
-        SurfaceType::Refractive {
-            index,
-            transparency,
-        } => {
-            let mut refraction_color = na::Vector3::zeros();
-            let kr = fresnel(ray.direction, normal, index);
-            let surface_color = material
-                .color
-                .color(&intersection.object.texture_coords(&hit_point));
-
-            if kr < T::one() {
-                let transmission_ray = Ray::create_transmission(
-                    normal,
-                    ray.direction,
-                    hit_point,
-                    scene.shadow_bias,
-                    index,
-                )
-                .unwrap();
-                refraction_color = cast_ray(scene, &transmission_ray, depth + 1);
+    let emitted = if sampled_emission || !is_sampled_emitter(intersection.object) {
+        material.emitted()
+    } else {
+        na::Vector3::zeros()
+    };
+
+    emitted
+        + match material.surface {
+            SurfaceType::Diffuse => {
+                shade_diffuse(scene, intersection.object, hit_point, normal, sampler)
+                    + shade_indirect(
+                        scene,
+                        intersection.object,
+                        hit_point,
+                        normal,
+                        depth,
+                        sampler,
+                    )
+            }
+            SurfaceType::Reflective { reflectivity } => {
+                let mut color =
+                    shade_diffuse(scene, intersection.object, hit_point, normal, sampler)
+                        + shade_indirect(
+                            scene,
+                            intersection.object,
+                            hit_point,
+                            normal,
+                            depth,
+                            sampler,
+                        );
+
+                let reflection_ray =
+                    Ray::create_reflection(normal, ray.direction, hit_point, scene.shadow_bias);
+
+                color *= T::one() - reflectivity;
+                color += cast_ray(scene, &reflection_ray, depth + 1, sampler) * reflectivity;
+                color
             }
+            SurfaceType::Refractive {
+                index,
+                transparency,
+            } => {
+                let mut refraction_color = na::Vector3::zeros();
+                let kr = fresnel(ray.direction, normal, index);
+                let surface_color = material
+                    .color
+                    .color(&intersection.object.texture_coords(&hit_point));
 
-            let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit_point, scene.shadow_bias);
-            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1);
-            let mut color = reflection_color * kr + refraction_color * (T::one() - kr);
-            color.component_mul_assign(&(surface_color * transparency));
-            color
+                if kr < T::one() {
+                    let transmission_ray = Ray::create_transmission(
+                        normal,
+                        ray.direction,
+                        hit_point,
+                        scene.shadow_bias,
+                        index,
+                    )
+                    .unwrap();
+                    refraction_color = cast_ray(scene, &transmission_ray, depth + 1, sampler);
+                }
+
+                let reflection_ray =
+                    Ray::create_reflection(normal, ray.direction, hit_point, scene.shadow_bias);
+                let reflection_color = cast_ray(scene, &reflection_ray, depth + 1, sampler);
+                let mut color = reflection_color * kr + refraction_color * (T::one() - kr);
+                color.component_mul_assign(&(surface_color * transparency));
+                color
+            }
         }
-    }
 }
 
-fn cast_ray<T>(scene: &Scene<T>, ray: &Ray<T>, depth: u32) -> na::Vector3<T>
+fn cast_ray<T, S>(scene: &Scene<T>, ray: &Ray<T>, depth: u32, sampler: &mut S) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
+{
+    trace_path(scene, ray, depth, sampler, true)
+}
+
+fn trace_path<T, S>(
+    scene: &Scene<T>,
+    ray: &Ray<T>,
+    depth: u32,
+    sampler: &mut S,
+    sampled_emission: bool,
+) -> na::Vector3<T>
 where
     T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
 {
     if depth >= scene.max_recursion_depth {
         return na::Vector3::zeros();
     }
 
-    let intersection = scene.trace(ray);
-    intersection
-        .map(|i| calculate_color(scene, ray, &i, depth))
-        .unwrap_or(na::Vector3::zeros())
+    match scene.trace(ray) {
+        Some(i) => calculate_color(scene, ray, &i, depth, sampler, sampled_emission),
+        None => match &scene.environment {
+            Some(environment) if sampled_emission => environment.radiance(&ray.direction),
+            _ => na::Vector3::zeros(),
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    // every sample of a tile is taken before moving on to the next tile
+    #[default]
+    Full,
+    // one sample per pixel across the whole image per pass, refining until the scene's sample
+    // count is reached or the time budget runs out
+    Progressive {
+        time_budget: Option<Duration>,
+    },
+}
+
+// keeps sampling the pixels whose estimated error is still above a threshold, once every pixel
+// has the scene's sample count
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    // standard error of a pixel's mean luminance, relative to that mean, at which it is done
+    pub threshold: f64,
+    pub max_samples: u32,
+}
+
+// how a render is carried out, as opposed to what the scene looks like. the image comes out bit
+// for bit the same for the same scene and seed whatever the number of threads or the tile order.
+// the tile size only matters for filters reaching past the pixel a sample is in, which add up
+// their overlapping tiles in a different grouping, and cancelling or running out of time
+// naturally leaves a different image depending on how far the render got
+#[derive(Clone)]
+pub struct RenderOptions {
+    pub threads: usize,
+    // width and height of the blocks of pixels each worker renders at a time
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub mode: RenderMode,
+    pub adaptive: Option<AdaptiveSampling>,
+    // called on the rendering thread each time a tile finishes
+    pub progress: Option<ProgressCallback>,
+    // called on the rendering thread after each progressive pass
+    pub preview: Option<PreviewCallback>,
+    pub cancel: CancelToken,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            threads: num_cpus::get(),
+            tile_size: 16,
+            tile_order: TileOrder::default(),
+            mode: RenderMode::default(),
+            adaptive: None,
+            progress: None,
+            preview: None,
+            cancel: CancelToken::new(),
+        }
+    }
+}
+
+impl fmt::Debug for RenderOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenderOptions")
+            .field("threads", &self.threads)
+            .field("tile_size", &self.tile_size)
+            .field("tile_order", &self.tile_order)
+            .field("mode", &self.mode)
+            .field("adaptive", &self.adaptive)
+            .field("progress", &self.progress.is_some())
+            .field("preview", &self.preview.is_some())
+            .field("cancel", &self.cancel)
+            .finish()
+    }
+}
+
+// splats the given samples of a pixel onto the film and returns their statistics
+fn render_pixel<T, S>(
+    scene: &Scene<T>,
+    x: u32,
+    y: u32,
+    samples: Range<u32>,
+    sampler: &mut S,
+    film: &mut FilmTile,
+) -> PixelStats
+where
+    T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
+{
+    let mut stats = PixelStats::default();
+    for s in samples {
+        sampler.start_sample(x, y, s);
+        let offset = next_2d(sampler);
+        let lens_sample = next_2d(sampler);
+        let ray = Ray::new_prime(x, y, &offset, &lens_sample, scene);
+
+        let color = cast_ray(scene, &ray, 0, sampler).map(|c| c.to_f64().unwrap());
+        let position =
+            na::Point2::new(f64::from(x), f64::from(y)) + offset.map(|o| o.to_f64().unwrap());
+        film.add_sample(position, &color);
+        stats.add(luminance(&color));
+    }
+    stats
+}
+
+// the pixels of a tile that still need samples, and which ones
+type TileWork = Vec<(u32, u32, Range<u32>)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderError {
+    // a worker panicked while rendering the tile
+    WorkerPanicked { tile: Tile, message: String },
+    // fewer tiles came back from the workers than were handed out
+    MissingTiles { missing: usize },
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::WorkerPanicked { tile, message } => write!(
+                f,
+                "render worker panicked on the tile at {}, {}: {}",
+                tile.x, tile.y, message
+            ),
+            RenderError::MissingTiles { missing } => {
+                write!(f, "{} tiles were never rendered", missing)
+            }
+        }
+    }
+}
+
+impl Error for RenderError {}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+// none if the render was cancelled before the tile was finished
+fn render_tile<T>(
+    scene: &Scene<T>,
+    tile: Tile,
+    work: &TileWork,
+    cancel: &CancelToken,
+) -> Option<(FilmTile, Vec<PixelStats>)>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let mut sampler = scene.sampler.sampler(scene.samples, scene.seed);
+    let mut film = FilmTile::new(tile, scene.width, scene.height, scene.filter);
+    let stats = work
+        .iter()
+        .map(|(x, y, samples)| {
+            if cancel.is_cancelled() {
+                return None;
+            }
+            Some(render_pixel(
+                scene,
+                *x,
+                *y,
+                samples.clone(),
+                &mut *sampler,
+                &mut film,
+            ))
+        })
+        .collect::<Option<_>>()?;
+    Some((film, stats))
+}
+
+// linear rec. 709 radiance for every pixel, without any clamping or encoding
+pub fn render_hdr<T>(scene: Scene<T>) -> Result<Rgb32FImage, RenderError>
+where
+    T: na::RealField + ToPrimitive,
+{
+    render_hdr_with(scene, &RenderOptions::default())
+}
+
+pub fn render_hdr_with<T>(
+    scene: Scene<T>,
+    options: &RenderOptions,
+) -> Result<Rgb32FImage, RenderError>
+where
+    T: na::RealField + ToPrimitive,
+{
+    Ok(render_film(scene, options)?.to_image())
+}
+
+// also returns how many samples each pixel took, for a heatmap of where adaptive sampling spent
+// its time
+pub fn render_hdr_with_sample_counts<T>(
+    scene: Scene<T>,
+    options: &RenderOptions,
+) -> Result<(Rgb32FImage, SampleCounts), RenderError>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let film = render_film(scene, options)?;
+    Ok((film.to_image(), film.sample_counts()))
 }
 
-pub fn render<T>(scene: Scene<T>) -> RgbImage
+fn render_film<T>(mut scene: Scene<T>, options: &RenderOptions) -> Result<Film, RenderError>
 where
     T: na::RealField + ToPrimitive,
 {
-    let Scene {
-        width,
-        height,
-        samples,
-        ..
-    } = scene;
+    let start = Instant::now();
+    scene.build_bvh();
+    scene.find_emitters();
 
-    let img = Arc::new(Mutex::new(RgbImage::new(width, height)));
+    let (width, height, samples) = (scene.width, scene.height, scene.samples);
+    let tiles = tiles(width, height, options.tile_size, options.tile_order);
     let scene = Arc::new(scene);
-    let pool = ThreadPool::new(num_cpus::get());
+    let pool = ThreadPool::new(options.threads.max(1));
+
+    let (batch, time_budget) = match options.mode {
+        RenderMode::Full => (samples.max(1), None),
+        RenderMode::Progressive { time_budget } => (1, time_budget),
+    };
+    // without adaptive sampling every pixel stops at the scene's sample count
+    let limit = options
+        .adaptive
+        .map_or(samples, |adaptive| adaptive.max_samples.max(samples));
+    let passes = limit.div_ceil(batch);
+    let needs_samples = |film: &Film, x, y| {
+        let stats = film.stats(x, y);
+        stats.count() < samples
+            || options.adaptive.is_some_and(|adaptive| {
+                stats.count() < limit && stats.relative_error() > adaptive.threshold
+            })
+    };
+
+    // tiles are added to the film row by row, whatever order they are rendered in
+    let mut rows = tiles.clone();
+    rows.sort_by_key(|tile| (tile.y, tile.x));
+    let ranks = tiles
+        .iter()
+        .map(|tile| {
+            rows.binary_search_by_key(&(tile.y, tile.x), |t| (t.y, t.x))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    let mut film = Film::new(width, height);
+    let mut progress = Progress {
+        total_passes: passes,
+        total_tiles: tiles.len() * passes as usize,
+        total_pixels: u64::from(width * height) * u64::from(passes),
+        ..Progress::default()
+    };
+    for _ in 0..passes {
+        let out_of_time = time_budget.is_some_and(|budget| start.elapsed() >= budget);
+        if options.cancel.is_cancelled() || (progress.completed_passes > 0 && out_of_time) {
+            break;
+        }
+
+        // workers fill their own buffers and hand them back, so nothing is shared while rendering
+        let (sender, receiver) = mpsc::channel();
+        let mut converged = true;
+        let mut dispatched = 0;
+        for (&tile, &rank) in tiles.iter().zip(&ranks) {
+            let work = tile
+                .pixels()
+                .filter(|&(x, y)| needs_samples(&film, x, y))
+                .map(|(x, y)| {
+                    let count = film.stats(x, y).count();
+                    (x, y, count..(count + batch).min(limit))
+                })
+                .collect::<TileWork>();
+            dispatched += 1;
+            if work.is_empty() {
+                sender.send((rank, tile, work, Ok(None))).unwrap();
+                continue;
+            }
+            converged = false;
 
-    for x in 0..width {
-        for y in 0..height {
-            let img = img.clone();
             let scene = scene.clone();
+            let sender = sender.clone();
+            let cancel = options.cancel.clone();
             pool.execute(move || {
-                let mut color = na::Vector3::zeros();
-                for s in 0..samples {
-                    let ray = Ray::new_prime(x, y, s, &scene);
+                // a panic is sent back as an error instead of taking the worker down
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    render_tile(&scene, tile, &work, &cancel)
+                }))
+                .map_err(|panic| panic_message(panic.as_ref()));
+                // the receiver is only gone if the render already failed
+                sender.send((rank, tile, work, result)).ok();
+            });
+        }
+        drop(sender);
+        if converged {
+            // every pixel is below the threshold, so the passes that are left have nothing to do
+            progress.total_passes = progress.completed_passes;
+            progress.total_tiles = progress.completed_tiles;
+            progress.total_pixels = progress.completed_pixels;
+            if let Some(callback) = &options.progress {
+                callback(&progress);
+            }
+            break;
+        }
 
-                    color += cast_ray(&scene, &ray, 0);
+        // the filter makes neighboring tiles overlap, so tiles that finish before the ones above
+        // them are held back to keep the sums bit for bit the same whatever the thread count
+        let mut received = 0;
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (rank, tile, work, result) in receiver {
+            received += 1;
+            let finished = match result {
+                Ok(finished) => finished,
+                Err(message) => return Err(RenderError::WorkerPanicked { tile, message }),
+            };
+            // no result with work left to do means the tile was cancelled before it was finished
+            let cancelled = finished.is_none() && !work.is_empty();
+            pending.insert(rank, finished.map(|result| (work, result)));
+            while let Some(finished) = pending.remove(&next) {
+                if let Some((work, (film_tile, stats))) = finished {
+                    film.add_tile(&film_tile);
+                    for ((x, y, _), stats) in work.iter().zip(&stats) {
+                        film.add_stats(*x, *y, stats);
+                    }
                 }
-                color /= T::from_u32(samples).unwrap();
+                next += 1;
+            }
+            if cancelled {
+                continue;
+            }
+            progress.completed_tiles += 1;
+            progress.completed_pixels += u64::from(tile.width * tile.height);
+            progress.elapsed = start.elapsed();
+            if let Some(callback) = &options.progress {
+                callback(&progress);
+            }
+        }
+        if received < dispatched {
+            return Err(RenderError::MissingTiles {
+                missing: dispatched - received,
+            });
+        }
 
-                let mut img = img.lock().unwrap();
-                img.put_pixel(x, y, vec3_to_rgb(color));
+        progress.completed_passes += 1;
+        if let (Some(preview), RenderMode::Progressive { .. }) = (&options.preview, options.mode) {
+            preview(&film.to_image(), progress.completed_passes);
+        }
+    }
+
+    Ok(film)
+}
+
+// a render running on its own thread, so a gui can poll it and stay responsive
+#[derive(Debug)]
+pub struct RenderHandle {
+    progress: Arc<Mutex<Progress>>,
+    cancel: CancelToken,
+    thread: thread::JoinHandle<Result<Film, RenderError>>,
+}
+
+impl RenderHandle {
+    pub fn progress(&self) -> Progress {
+        *self.progress.lock().unwrap()
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    // waits for the render, which is only partially filled in if it was cancelled
+    pub fn join(self) -> Result<Rgb32FImage, RenderError> {
+        Ok(self.join_film()?.to_image())
+    }
+
+    pub fn join_with_sample_counts(self) -> Result<(Rgb32FImage, SampleCounts), RenderError> {
+        let film = self.join_film()?;
+        Ok((film.to_image(), film.sample_counts()))
+    }
+
+    // a panic on the render thread itself can only come from one of the callbacks, so it is
+    // passed on to the caller that supplied them
+    fn join_film(self) -> Result<Film, RenderError> {
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+pub fn render_in_background<T>(scene: Scene<T>, options: RenderOptions) -> RenderHandle
+where
+    T: na::RealField + ToPrimitive,
+{
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    let cancel = options.cancel.clone();
+
+    let shared = progress.clone();
+    let callback = options.progress.clone();
+    let options = RenderOptions {
+        progress: Some(Arc::new(move |p: &Progress| {
+            *shared.lock().unwrap() = *p;
+            if let Some(callback) = &callback {
+                callback(p);
+            }
+        })),
+        ..options
+    };
+    let thread = thread::spawn(move || render_film(scene, &options));
+
+    RenderHandle {
+        progress,
+        cancel,
+        thread,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RenderReport {
+    // pixels with a nan or infinite channel, which are written out as black
+    pub invalid_pixels: u32,
+}
+
+pub fn render<T>(scene: Scene<T>) -> Result<RgbImage, RenderError>
+where
+    T: na::RealField + ToPrimitive,
+{
+    Ok(render_with_report(scene)?.0)
+}
+
+pub fn render_with_report<T>(scene: Scene<T>) -> Result<(RgbImage, RenderReport), RenderError>
+where
+    T: na::RealField + ToPrimitive,
+{
+    render_with(scene, &RenderOptions::default())
+}
+
+pub fn render_with<T>(
+    scene: Scene<T>,
+    options: &RenderOptions,
+) -> Result<(RgbImage, RenderReport), RenderError>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let (tone_mapping, primaries, dither) = (scene.tone_mapping, scene.primaries, scene.dither);
+    let hdr = render_hdr_with(scene, options)?;
+    Ok(develop(&hdr, tone_mapping, primaries, dither))
+}
+
+// turns linear radiance into a displayable 8-bit image
+pub fn develop(
+    hdr: &Rgb32FImage,
+    tone_mapping: ToneMapping,
+    primaries: Primaries,
+    dither: Dither,
+) -> (RgbImage, RenderReport) {
+    let mut report = RenderReport::default();
+    let image = RgbImage::from_fn(hdr.width(), hdr.height(), |x, y| {
+        let mut color = na::Vector3::from(hdr.get_pixel(x, y).0);
+        if !color.iter().all(|c| c.is_finite()) {
+            report.invalid_pixels += 1;
+            color = na::Vector3::zeros();
+        }
+        let color = tone_mapping.apply(primaries.from_rec709(color));
+        vec3_to_rgb(color, dither.offset(x, y))
+    });
+    (image, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nalgebra as na;
+
+    use super::*;
+    use crate::{
+        bvh::Aabb,
+        camera::{Camera, FovAxis},
+        coloration::Color,
+        environment::{ConstantEnvironment, Environment},
+        filter::{Filter, FilterShape},
+        lights::DirectionalLight,
+        objects::{Plane, Sphere, TriangleMesh},
+        Material,
+    };
+
+    fn white() -> Material<f64> {
+        Material {
+            color: Box::new(Color {
+                color: na::Vector3::new(1.0, 1.0, 1.0),
+            }),
+            surface: SurfaceType::Diffuse,
+            albedo: 1.0,
+            emission: na::Vector3::zeros(),
+            emission_strength: 0.0,
+        }
+    }
+
+    // the camera looks at the underside of a sphere, which only the floor below can light
+    fn scene(integrator: Integrator) -> Scene<f64> {
+        let mut scene = Scene::new(
+            9,
+            9,
+            Camera {
+                eye: na::Point3::new(0.0, 0.5, 4.0),
+                target: na::Point3::new(0.0, 1.2, 0.0),
+                up: na::Vector3::new(0.0, 1.0, 0.0),
+                fov: 10.0,
+                fov_axis: FovAxis::Vertical,
+                aperture: 0.0,
+                focus_distance: 1.0,
+            },
+        );
+        scene.samples = 16;
+        scene.objects = vec![
+            Box::new(Sphere {
+                center: na::Point3::new(0.0, 2.0, 0.0),
+                radius: 1.0,
+                material: white(),
+            }),
+            Box::new(Plane {
+                origin: na::Point3::origin(),
+                normal: na::Vector3::new(0.0, -1.0, 0.0),
+                material: white(),
+            }),
+        ];
+        scene.lights = vec![Box::new(DirectionalLight {
+            direction: na::Vector3::new(0.0, -1.0, 0.0),
+            color: na::Vector3::new(1.0, 1.0, 1.0),
+            intensity: 10.0,
+        })];
+        scene.integrator = integrator;
+        scene
+    }
+
+    #[test]
+    fn hdr_render_keeps_radiance_above_one() {
+        // looks down at the floor, which the light makes brighter than white
+        let mut scene = scene(Integrator::Whitted);
+        scene.objects.remove(0);
+        scene.camera.eye = na::Point3::new(0.0, 1.0, 4.0);
+        scene.camera.target = na::Point3::origin();
+
+        let hdr = render_hdr(scene).unwrap();
+        assert!(hdr.get_pixel(4, 4).0.iter().all(|&c| c > 1.0));
+    }
+
+    #[test]
+    fn path_tracing_adds_indirect_light() {
+        let whitted = render(scene(Integrator::Whitted)).unwrap();
+        assert_eq!(whitted.get_pixel(4, 4).0, [0, 0, 0]);
+
+        let path_traced = render(scene(Integrator::PathTracing {
+            russian_roulette_depth: 3,
+        }))
+        .unwrap();
+        assert!(path_traced.get_pixel(4, 4).0.iter().all(|&c| c > 0));
+    }
+
+    #[test]
+    fn emissive_objects_light_the_scene() {
+        // looks down at the floor below a sphere, with no lights in the scene
+        let scene = |emission_strength| {
+            let mut scene = scene(Integrator::Whitted);
+            scene.lights.clear();
+            scene.camera.eye = na::Point3::new(0.0, 1.0, 4.0);
+            scene.camera.target = na::Point3::origin();
+            scene.objects[0] = Box::new(Sphere {
+                center: na::Point3::new(0.0, 2.0, 0.0),
+                radius: 1.0,
+                material: Material {
+                    emission: na::Vector3::new(1.0, 1.0, 1.0),
+                    emission_strength,
+                    ..white()
+                },
+            });
+            scene
+        };
+
+        let dark = render(scene(0.0)).unwrap();
+        assert_eq!(dark.get_pixel(4, 4).0, [0, 0, 0]);
+
+        let lit = render(scene(5.0)).unwrap();
+        assert!(lit.get_pixel(4, 4).0.iter().all(|&c| c > 0));
+
+        // the same light as a mesh, facing the floor
+        let mut scene = scene(0.0);
+        scene.objects[0] = Box::new(TriangleMesh::new(
+            vec![
+                na::Point3::new(-1.0, 1.0, -1.0),
+                na::Point3::new(1.0, 1.0, -1.0),
+                na::Point3::new(1.0, 1.0, 1.0),
+                na::Point3::new(-1.0, 1.0, 1.0),
+            ],
+            vec![],
+            vec![],
+            vec![[0, 1, 2], [0, 2, 3]],
+            Material {
+                emission: na::Vector3::new(1.0, 1.0, 1.0),
+                emission_strength: 5.0,
+                ..white()
+            },
+        ));
+        let lit = render(scene).unwrap();
+        assert!(lit.get_pixel(4, 4).0.iter().all(|&c| c > 0));
+    }
+
+    #[test]
+    fn environment_lights_missed_rays_and_diffuse_surfaces() {
+        // the top of the image sees the sky and the center sees the floor, with no lights
+        let render_with = |environment: Option<Box<dyn Environment<f64>>>| {
+            let mut scene = scene(Integrator::Whitted);
+            scene.lights.clear();
+            scene.objects.remove(0);
+            scene.camera.eye = na::Point3::new(0.0, 1.0, 4.0);
+            scene.camera.target = na::Point3::origin();
+            scene.camera.fov = 60.0;
+            scene.environment = environment;
+            render(scene).unwrap()
+        };
+
+        let dark = render_with(None);
+        assert_eq!(dark.get_pixel(4, 0).0, [0, 0, 0]);
+        assert_eq!(dark.get_pixel(4, 4).0, [0, 0, 0]);
+
+        let lit = render_with(Some(Box::new(ConstantEnvironment {
+            color: na::Vector3::new(0.5, 0.5, 0.5),
+        })));
+        let sky = lit.get_pixel(4, 0).0;
+        assert!(sky.iter().all(|&c| c > 0) && sky[0] == sky[1] && sky[1] == sky[2]);
+        assert!(lit.get_pixel(4, 4).0.iter().all(|&c| c > 0));
+    }
+
+    // stands in for a bug that brings down a worker
+    #[derive(Debug)]
+    struct Panicking(Material<f64>);
+
+    impl Intersectable<f64> for Panicking {
+        fn intersect(&self, _ray: &Ray<f64>) -> Option<f64> {
+            panic!("broken object")
+        }
+
+        fn surface_normal(&self, _hit_point: &na::Point3<f64>) -> na::Vector3<f64> {
+            na::Vector3::y()
+        }
+
+        fn texture_coords(&self, _hit_point: &na::Point3<f64>) -> na::Vector2<f64> {
+            na::Vector2::zeros()
+        }
+
+        fn material(&self) -> &Material<f64> {
+            &self.0
+        }
+
+        fn bounding_box(&self) -> Option<Aabb<f64>> {
+            None
+        }
+    }
+
+    #[test]
+    fn worker_panics_are_returned_as_errors() {
+        let mut scene = scene(Integrator::Whitted);
+        scene.objects.push(Box::new(Panicking(white())));
+        match render_hdr(scene) {
+            Err(RenderError::WorkerPanicked { message, .. }) => {
+                assert_eq!(message, "broken object")
+            }
+            other => panic!("expected a worker panic, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn progress_is_reported_and_renders_can_be_cancelled() {
+        // looks down at the lit floor
+        let scene = || {
+            let mut scene = scene(Integrator::Whitted);
+            scene.camera.eye = na::Point3::new(0.0, 1.0, 4.0);
+            scene.camera.target = na::Point3::origin();
+            scene
+        };
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let options = RenderOptions {
+            tile_size: 4,
+            progress: Some(Arc::new(move |p: &Progress| {
+                recorded.lock().unwrap().push(*p)
+            })),
+            ..RenderOptions::default()
+        };
+        let image = render_in_background(scene(), options).join().unwrap();
+        assert!(image.pixels().any(|p| p.0 != [0.0; 3]));
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 9);
+        assert!(calls.last().unwrap().is_complete());
+        assert_eq!(calls.last().unwrap().remaining(), Some(Duration::ZERO));
+        assert!(calls
+            .windows(2)
+            .all(|w| w[0].completed_pixels < w[1].completed_pixels));
+
+        let options = RenderOptions::default();
+        options.cancel.cancel();
+        let handle = render_in_background(scene(), options);
+        while !handle.is_finished() {
+            thread::yield_now();
+        }
+        assert_eq!(handle.progress().completed_pixels, 0);
+        assert!(handle.join().unwrap().pixels().all(|p| p.0 == [0.0; 3]));
+    }
+
+    #[test]
+    fn progressive_passes_refine_until_the_sample_count_or_time_budget() {
+        let previews = Arc::new(Mutex::new(Vec::new()));
+        let recorded = previews.clone();
+        let options = RenderOptions {
+            mode: RenderMode::Progressive { time_budget: None },
+            preview: Some(Arc::new(move |image: &Rgb32FImage, samples| {
+                recorded.lock().unwrap().push((image.clone(), samples))
+            })),
+            ..RenderOptions::default()
+        };
+        let image = render_hdr_with(scene(Integrator::Whitted), &options).unwrap();
+
+        {
+            let previews = previews.lock().unwrap();
+            let samples = previews.iter().map(|p| p.1).collect::<Vec<_>>();
+            assert_eq!(samples, (1..=16).collect::<Vec<_>>());
+            assert_eq!(previews.last().unwrap().0, image);
+        }
+
+        let options = RenderOptions {
+            mode: RenderMode::Progressive {
+                time_budget: Some(Duration::ZERO),
+            },
+            ..options
+        };
+        render_hdr_with(scene(Integrator::Whitted), &options).unwrap();
+        assert_eq!(
+            previews.lock().unwrap().len(),
+            17,
+            "a zero budget should still finish one pass"
+        );
+    }
+
+    #[test]
+    fn wide_filters_spread_samples_across_edges() {
+        // a black floor under a grey sky, with the horizon crossing the image
+        let render_with = |filter| {
+            let mut scene = scene(Integrator::Whitted);
+            scene.lights.clear();
+            scene.objects.remove(0);
+            scene.objects[0] = Box::new(Plane {
+                origin: na::Point3::origin(),
+                normal: na::Vector3::new(0.0, -1.0, 0.0),
+                material: Material {
+                    albedo: 0.0,
+                    ..white()
+                },
             });
+            scene.camera.eye = na::Point3::new(0.0, 1.0, 4.0);
+            scene.camera.target = na::Point3::origin();
+            scene.camera.fov = 60.0;
+            scene.environment = Some(Box::new(ConstantEnvironment {
+                color: na::Vector3::new(0.5, 0.5, 0.5),
+            }));
+            scene.filter = filter;
+            render_hdr(scene).unwrap()
+        };
+        let not_black = |image: &Rgb32FImage| image.pixels().filter(|p| p.0 != [0.0; 3]).count();
+
+        let sharp = render_with(Filter::default());
+        assert_eq!(sharp.get_pixel(4, 0).0, [0.5; 3]);
+        assert_eq!(sharp.get_pixel(4, 8).0, [0.0; 3]);
+
+        for shape in [
+            FilterShape::Tent,
+            FilterShape::Gaussian { alpha: 2.0 },
+            FilterShape::MitchellNetravali {
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+            FilterShape::Lanczos { tau: 3.0 },
+        ] {
+            let soft = render_with(Filter { radius: 2.0, shape });
+            assert!(not_black(&soft) > not_black(&sharp), "{:?}", shape);
+            assert!(
+                (soft.get_pixel(4, 0).0[0] - 0.5).abs() < 1e-6,
+                "{:?}",
+                shape
+            );
+            assert_eq!(soft.get_pixel(4, 8).0, [0.0; 3], "{:?}", shape);
         }
     }
 
-    pool.join();
-    Arc::try_unwrap(img).unwrap().into_inner().unwrap()
+    #[test]
+    fn renders_only_depend_on_the_scene_and_seed() {
+        let scene = |seed| {
+            let mut scene = scene(Integrator::PathTracing {
+                russian_roulette_depth: 2,
+            });
+            scene.filter = Filter {
+                radius: 2.5,
+                shape: FilterShape::Gaussian { alpha: 2.0 },
+            };
+            scene.seed = seed;
+            scene
+        };
+        let render_on = |threads, tile_order, seed| {
+            let options = RenderOptions {
+                threads,
+                tile_size: 2,
+                tile_order,
+                adaptive: Some(AdaptiveSampling {
+                    threshold: 0.05,
+                    max_samples: 32,
+                }),
+                ..RenderOptions::default()
+            };
+            render_film(scene(seed), &options).unwrap()
+        };
+
+        // compares the film, whose sums have more bits than the image to go wrong in
+        let reference = render_on(1, TileOrder::Scanline, 7);
+        assert!(reference.to_image().pixels().any(|p| p.0 != [0.0; 3]));
+        for (threads, tile_order) in [
+            (1, TileOrder::Scanline),
+            (2, TileOrder::Spiral),
+            (5, TileOrder::Hilbert),
+            (16, TileOrder::Spiral),
+        ] {
+            assert!(
+                render_on(threads, tile_order, 7) == reference,
+                "{} threads, {:?}",
+                threads,
+                tile_order
+            );
+        }
+        assert!(render_on(4, TileOrder::Scanline, 8) != reference);
+    }
+
+    #[test]
+    fn adaptive_sampling_spends_samples_on_noisy_pixels() {
+        // the top of the image sees a flat sky and the center sees the floor it noisily lights
+        let scene = || {
+            let mut scene = scene(Integrator::Whitted);
+            scene.samples = 4;
+            scene.lights.clear();
+            scene.objects.remove(0);
+            scene.camera.eye = na::Point3::new(0.0, 1.0, 4.0);
+            scene.camera.target = na::Point3::origin();
+            scene.camera.fov = 60.0;
+            scene.environment = Some(Box::new(ConstantEnvironment {
+                color: na::Vector3::new(0.5, 0.5, 0.5),
+            }));
+            scene
+        };
+
+        let (_, counts) =
+            render_hdr_with_sample_counts(scene(), &RenderOptions::default()).unwrap();
+        assert!(counts.pixels().all(|p| p.0[0] == 4));
+
+        for mode in [
+            RenderMode::Full,
+            RenderMode::Progressive { time_budget: None },
+        ] {
+            let options = RenderOptions {
+                mode,
+                adaptive: Some(AdaptiveSampling {
+                    threshold: 1e-3,
+                    max_samples: 64,
+                }),
+                ..RenderOptions::default()
+            };
+            let (image, counts) = render_hdr_with_sample_counts(scene(), &options).unwrap();
+            assert_eq!(counts.get_pixel(4, 0).0[0], 4, "{:?}", mode);
+            assert_eq!(counts.get_pixel(4, 4).0[0], 64, "{:?}", mode);
+            assert!(counts.pixels().all(|p| (4..=64).contains(&p.0[0])));
+            assert!(image.get_pixel(4, 4).0.iter().all(|&c| c > 0.0));
+        }
+    }
 }
@@ -0,0 +1,462 @@
+use std::fmt::Debug;
+
+use nalgebra as na;
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+// hands out the random numbers for one pixel sample, one dimension at a time. the values only
+// depend on the seed, the pixel, the sample index and how many dimensions were drawn before, so
+// a render comes out the same however its samples are split between threads and passes
+pub trait Sampler: Debug + Send {
+    // starts the given sample of a pixel, going back to the first dimension
+    fn start_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn next_1d(&mut self) -> f64;
+
+    fn next_2d(&mut self) -> [f64; 2] {
+        [self.next_1d(), self.next_1d()]
+    }
+}
+
+pub fn next_1d<T, S>(sampler: &mut S) -> T
+where
+    T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
+{
+    T::from_f64(sampler.next_1d()).unwrap()
+}
+
+pub fn next_2d<T, S>(sampler: &mut S) -> na::Vector2<T>
+where
+    T: na::RealField + ToPrimitive,
+    S: Sampler + ?Sized,
+{
+    let [u, v] = sampler.next_2d();
+    na::Vector2::new(T::from_f64(u).unwrap(), T::from_f64(v).unwrap())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    // independent uniform values, noisy but without any structure to alias
+    Random,
+    // one sample in each cell of a grid per dimension, jittered within the cell
+    #[default]
+    Stratified,
+    // the halton low discrepancy sequence, shifted by a different offset in every pixel
+    Halton,
+    // pairs of dimensions from the (0, 2) sobol sequence, scrambled and shuffled per dimension
+    Sobol,
+}
+
+impl SamplerKind {
+    // stratified and sobol sampling is best with exactly samples_per_pixel samples, any further
+    // samples start over with new strata. a different seed gives different but equally good
+    // samples
+    pub fn sampler(self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        let samples = samples_per_pixel.max(1);
+        match self {
+            SamplerKind::Random => Box::new(RandomSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(samples, seed)),
+        }
+    }
+}
+
+// splitmix64's finalizer
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0, |h, &v| mix(h ^ v.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+// the top 53 bits as a float in [0, 1)
+fn to_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// kensler's hashed permutation of 0..length, so the i-th stratum can be picked without shuffling
+// a list
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        // values past the end are walked on until they land inside
+        if i < length {
+            return i.wrapping_add(seed) % length;
+        }
+    }
+}
+
+// where the current sample is and how many dimensions it has used
+#[derive(Debug, Clone, Copy)]
+struct SampleState {
+    seed: u64,
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> SampleState {
+        SampleState {
+            seed,
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start(&mut self, x: u32, y: u32, index: u32) {
+        *self = SampleState {
+            x,
+            y,
+            index,
+            dimension: 0,
+            ..*self
+        };
+    }
+
+    // hashes the pixel and dimension with some extra values, which advances the dimension
+    fn hash(&mut self, extra: &[u64]) -> u64 {
+        let pixel = [
+            self.seed,
+            u64::from(self.x),
+            u64::from(self.y),
+            u64::from(self.dimension),
+        ];
+        self.dimension += 1;
+        hash(&[hash(&pixel), hash(extra)])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RandomSampler {
+    state: SampleState,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> RandomSampler {
+        RandomSampler {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let index = u64::from(self.state.index);
+        to_unit(self.state.hash(&[index]))
+    }
+}
+
+// jittered stratification for any number of samples: 1d dimensions use samples_per_pixel
+// strata, 2d ones the smallest grid with at least that many cells, and each dimension visits its
+// strata in its own random order so the dimensions are not correlated
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    samples: u32,
+    grid: (u32, u32),
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> StratifiedSampler {
+        let samples = samples_per_pixel.max(1);
+        let columns = f64::from(samples).sqrt().ceil() as u32;
+        StratifiedSampler {
+            samples,
+            grid: (columns, samples.div_ceil(columns)),
+            state: SampleState::new(seed),
+        }
+    }
+
+    // the stratum out of count for the current sample, and a hash for jittering within it
+    fn stratum(&mut self, count: u32) -> (u32, u64) {
+        let (round, i) = (
+            self.state.index / self.samples,
+            self.state.index % self.samples,
+        );
+        // the permutation is shared by the samples of a round, the jitter is not
+        let bits = self.state.hash(&[u64::from(round)]);
+        (permute(i, count, bits as u32), hash(&[bits, u64::from(i)]))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let (stratum, jitter) = self.stratum(self.samples);
+        (f64::from(stratum) + to_unit(jitter)) / f64::from(self.samples)
+    }
+
+    fn next_2d(&mut self) -> [f64; 2] {
+        let (columns, rows) = self.grid;
+        let (stratum, jitter) = self.stratum(columns * rows);
+        [
+            (f64::from(stratum % columns) + to_unit(jitter)) / f64::from(columns),
+            (f64::from(stratum / columns) + to_unit(mix(jitter))) / f64::from(rows),
+        ]
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+// the digits of index in the given base, mirrored around the decimal point
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inverse_base = 1.0 / f64::from(base);
+    let (mut result, mut scale) = (0.0, inverse_base);
+    while index > 0 {
+        result += f64::from(index % base) * scale;
+        index /= base;
+        scale *= inverse_base;
+    }
+    result
+}
+
+// one prime base per dimension, with a random toroidal shift per pixel and dimension so
+// neighboring pixels do not repeat each other. dimensions past the last prime are random
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let (index, dimension) = (self.state.index, self.state.dimension as usize);
+        match PRIMES.get(dimension) {
+            Some(&base) => {
+                let shift = to_unit(self.state.hash(&[]));
+                (radical_inverse(base, index) + shift).fract()
+            }
+            None => to_unit(self.state.hash(&[u64::from(index)])),
+        }
+    }
+}
+
+// the first two dimensions of the sobol sequence, which together form a (0, 2) sequence
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_1(mut index: u32) -> u32 {
+    let (mut result, mut v) = (0, 1 << 31);
+    while index > 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn bits_to_unit(bits: u32) -> f64 {
+    f64::from(bits) / (1u64 << 32) as f64
+}
+
+// every dimension, or pair of dimensions, gets the same well stratified sobol points in its own
+// order and with its own random digit scrambling, which keeps the stratification and
+// decorrelates the dimensions
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    samples: u32,
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> SobolSampler {
+        SobolSampler {
+            samples: samples_per_pixel.max(1),
+            state: SampleState::new(seed),
+        }
+    }
+
+    // the shuffled point index for the current dimension, and a hash for scrambling it
+    fn point(&mut self) -> (u32, u64) {
+        let (round, i) = (
+            self.state.index / self.samples,
+            self.state.index % self.samples,
+        );
+        let bits = self.state.hash(&[u64::from(round)]);
+        let index =
+            round
+                .wrapping_mul(self.samples)
+                .wrapping_add(permute(i, self.samples, bits as u32));
+        (index, mix(bits))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let (index, scramble) = self.point();
+        bits_to_unit(sobol_0(index) ^ scramble as u32)
+    }
+
+    fn next_2d(&mut self) -> [f64; 2] {
+        let (index, scramble) = self.point();
+        [
+            bits_to_unit(sobol_0(index) ^ scramble as u32),
+            bits_to_unit(sobol_1(index) ^ (scramble >> 32) as u32),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Random,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    fn points(kind: SamplerKind, samples: u32, x: u32, y: u32) -> Vec<[f64; 2]> {
+        points_with_seed(kind, samples, x, y, 0)
+    }
+
+    fn points_with_seed(
+        kind: SamplerKind,
+        samples: u32,
+        x: u32,
+        y: u32,
+        seed: u64,
+    ) -> Vec<[f64; 2]> {
+        let mut sampler = kind.sampler(samples, seed);
+        (0..samples)
+            .map(|s| {
+                sampler.start_sample(x, y, s);
+                sampler.next_2d()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samplers_are_deterministic_and_in_range() {
+        for kind in KINDS {
+            let first = points(kind, 12, 3, 5);
+            assert_eq!(first, points(kind, 12, 3, 5), "{:?}", kind);
+            assert_ne!(first, points(kind, 12, 4, 5), "{:?}", kind);
+            assert_ne!(first, points_with_seed(kind, 12, 3, 5, 1), "{:?}", kind);
+
+            let mut sampler = kind.sampler(12, 0);
+            for s in 0..40 {
+                sampler.start_sample(1, 2, s);
+                for _ in 0..100 {
+                    let [u, v] = sampler.next_2d();
+                    let w = sampler.next_1d();
+                    assert!(
+                        [u, v, w].iter().all(|c| (0.0..1.0).contains(c)),
+                        "{:?}",
+                        kind
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn odd_sample_counts_are_stratified() {
+        // every row and column of an 8 x 8 grid gets one of 8 sobol points, and 8 stratified
+        // points fill 8 of the 9 cells of a 3 x 3 grid
+        let sobol = points(SamplerKind::Sobol, 8, 0, 0);
+        for axis in 0..2 {
+            let mut cells = sobol
+                .iter()
+                .map(|p| (p[axis] * 8.0) as u32)
+                .collect::<Vec<_>>();
+            cells.sort_unstable();
+            assert_eq!(cells, (0..8).collect::<Vec<_>>());
+        }
+
+        let stratified = points(SamplerKind::Stratified, 8, 0, 0);
+        let mut cells = stratified
+            .iter()
+            .map(|p| (p[1] * 3.0) as u32 * 3 + (p[0] * 3.0) as u32)
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells.dedup();
+        assert_eq!(cells.len(), 8);
+
+        // each sample lands somewhere else within its cell
+        let mut offsets = stratified
+            .iter()
+            .map(|p| ((p[0] * 3.0).fract(), (p[1] * 3.0).fract()))
+            .collect::<Vec<_>>();
+        offsets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        offsets.dedup();
+        assert_eq!(offsets.len(), 8);
+
+        for samples in [1, 2, 5, 7, 13] {
+            for s in 0..samples {
+                assert!(permute(s, samples, 1234) < samples);
+            }
+            let mut order = (0..samples)
+                .map(|s| permute(s, samples, 99))
+                .collect::<Vec<_>>();
+            order.sort_unstable();
+            assert_eq!(order, (0..samples).collect::<Vec<_>>());
+        }
+    }
+}
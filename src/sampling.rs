@@ -0,0 +1,72 @@
+use nalgebra as na;
+use num::ToPrimitive;
+
+// maps a point in the unit square onto the unit disk, preserving relative areas
+pub fn concentric_disk<T>(sample: &na::Vector2<T>) -> na::Vector2<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let offset = sample * na::convert::<f64, T>(2.0) - na::Vector2::repeat(T::one());
+    if offset.x == T::zero() && offset.y == T::zero() {
+        return na::Vector2::zeros();
+    }
+
+    let quarter_pi = T::frac_pi_4();
+    let (radius, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, quarter_pi * (offset.y / offset.x))
+    } else {
+        (
+            offset.y,
+            T::frac_pi_2() - quarter_pi * (offset.x / offset.y),
+        )
+    };
+    na::Vector2::new(theta.cos(), theta.sin()) * radius
+}
+
+// cosine weighted direction in the hemisphere around the normal, with pdf cos(theta) / pi
+pub fn cosine_hemisphere<T>(normal: &na::Vector3<T>, sample: &na::Vector2<T>) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let disk = concentric_disk(sample);
+    let z = (T::one() - disk.norm_squared()).max(T::zero()).sqrt();
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    (tangent * disk.x + bitangent * disk.y + normal * z).normalize()
+}
+
+// two unit vectors perpendicular to the normal and each other
+pub fn orthonormal_basis<T>(normal: &na::Vector3<T>) -> (na::Vector3<T>, na::Vector3<T>)
+where
+    T: na::RealField + ToPrimitive,
+{
+    let helper = if normal.x.abs() > na::convert(0.9) {
+        na::Vector3::y()
+    } else {
+        na::Vector3::x()
+    };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+    (tangent, bitangent)
+}
+
+// uniformly distributed point on the unit sphere
+pub fn uniform_sphere<T>(sample: &na::Vector2<T>) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let z = T::one() - sample.x * na::convert(2.0);
+    let r = (T::one() - z * z).max(T::zero()).sqrt();
+    let phi = T::two_pi() * sample.y;
+    na::Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// uniformly distributed barycentric coordinates on a triangle
+pub fn uniform_triangle<T>(sample: &na::Vector2<T>) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let su = sample.x.sqrt();
+    let u = T::one() - su;
+    let v = sample.y * su;
+    na::Vector3::new(T::one() - u - v, u, v)
+}
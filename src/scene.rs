@@ -1,7 +1,37 @@
 use nalgebra as na;
 use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
 
-use crate::{intersection::Intersection, lights::Light, objects::Intersectable, ray::Ray};
+use crate::{
+    bvh::Bvh,
+    camera::Camera,
+    color_convert::{Dither, Primaries},
+    environment::Environment,
+    filter::Filter,
+    intersection::Intersection,
+    lights::Light,
+    objects::Intersectable,
+    ray::Ray,
+    sampler::SamplerKind,
+    tone_mapping::ToneMapping,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    // direct lighting plus perfect reflection and refraction
+    Whitted,
+    // adds indirect diffuse bounces, terminated with russian roulette after the given depth
+    PathTracing { russian_roulette_depth: u32 },
+}
+
+// emissive objects that can be sampled are lit from like lights, the rest only when hit
+pub(crate) fn is_sampled_emitter<T>(object: &dyn Intersectable<T>) -> bool
+where
+    T: na::RealField + ToPrimitive,
+{
+    object.material().is_emissive() && object.can_sample_surface()
+}
 
 #[derive(Debug)]
 pub struct Scene<T>
@@ -11,21 +41,86 @@ where
     pub width: u32,
     pub height: u32,
     pub samples: u32,
-    pub fov: T,
+    pub camera: Camera<T>,
     pub objects: Vec<Box<dyn Intersectable<T>>>,
     pub lights: Vec<Box<dyn Light<T>>>,
     pub shadow_bias: T,
     pub max_recursion_depth: u32,
+    pub integrator: Integrator,
+    pub environment: Option<Box<dyn Environment<T>>>,
+    pub tone_mapping: ToneMapping,
+    pub primaries: Primaries,
+    pub dither: Dither,
+    pub sampler: SamplerKind,
+    // how samples are weighted into the pixels around them
+    pub filter: Filter,
+    // everything random in a render is derived from this, so the same scene and seed always
+    // give the same image
+    pub seed: u64,
+    // built by the renderer once the scene can no longer change, trace falls back to testing
+    // every object without it
+    bvh: Option<Bvh<T>>,
+    // the objects whose surfaces shading samples light from, found along with the bvh
+    emitters: Vec<usize>,
 }
 
 impl<T> Scene<T>
 where
     T: na::RealField + ToPrimitive,
 {
-    pub fn trace(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
-        self.objects
+    // an empty scene with the same defaults as a scene file, to be filled in through its fields
+    pub fn new(width: u32, height: u32, camera: Camera<T>) -> Scene<T> {
+        Scene {
+            width,
+            height,
+            samples: 1,
+            camera,
+            objects: Vec::new(),
+            lights: Vec::new(),
+            shadow_bias: na::convert(1e-9),
+            max_recursion_depth: 8,
+            integrator: Integrator::Whitted,
+            environment: None,
+            tone_mapping: ToneMapping::default(),
+            primaries: Primaries::default(),
+            dither: Dither::default(),
+            sampler: SamplerKind::default(),
+            filter: Filter::default(),
+            seed: 0,
+            bvh: None,
+            emitters: Vec::new(),
+        }
+    }
+
+    pub(crate) fn build_bvh(&mut self) {
+        let bounds = self
+            .objects
             .iter()
-            .filter_map(|s| s.intersect(ray).map(|d| Intersection::new(d, s.as_ref())))
-            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
+            .map(|o| o.bounding_box())
+            .collect::<Vec<_>>();
+        self.bvh = Some(Bvh::build(&bounds));
+    }
+
+    pub(crate) fn find_emitters(&mut self) {
+        self.emitters = (0..self.objects.len())
+            .filter(|&i| is_sampled_emitter(self.objects[i].as_ref()))
+            .collect();
+    }
+
+    pub(crate) fn emitters(&self) -> impl Iterator<Item = &dyn Intersectable<T>> {
+        self.emitters.iter().map(move |&i| self.objects[i].as_ref())
+    }
+
+    pub fn trace(&self, ray: &Ray<T>) -> Option<Intersection<'_, T>> {
+        match &self.bvh {
+            Some(bvh) => bvh
+                .intersect(ray, |i| self.objects[i].intersect(ray))
+                .map(|(i, d)| Intersection::new(d, self.objects[i].as_ref())),
+            None => self
+                .objects
+                .iter()
+                .filter_map(|s| s.intersect(ray).map(|d| Intersection::new(d, s.as_ref())))
+                .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap()),
+        }
     }
 }
@@ -0,0 +1,1433 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use image::DynamicImage;
+use nalgebra as na;
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{Camera, FovAxis},
+    color_convert::{ColorSpace, Dither, Primaries},
+    coloration::{Color, Coloration, Texture},
+    environment::{ConstantEnvironment, Environment, GradientEnvironment, ImageEnvironment},
+    filter::{Filter, FilterShape},
+    lights::{DirectionalLight, Light, RectangularLight, SphericalLight, SpotLight},
+    material::{Material, SurfaceType},
+    obj::{absolute, load_obj, ObjError},
+    objects::{Intersectable, Plane, Sphere, Triangle, TriangleMesh},
+    sampler::SamplerKind,
+    scene::{Integrator, Scene},
+    sky::PreethamSky,
+    tone_mapping::{ToneMapOperator, ToneMapping},
+};
+
+// everything needed to build a scene, as plain data that can be written to and read from files.
+// relative paths are resolved against the directory of the scene file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default = "default_shadow_bias")]
+    pub shadow_bias: f64,
+    #[serde(default = "default_max_recursion_depth")]
+    pub max_recursion_depth: u32,
+    #[serde(default = "default_integrator")]
+    pub integrator: Integrator,
+    #[serde(default)]
+    pub environment: Option<EnvironmentDescription>,
+    #[serde(default)]
+    pub tone_mapping: ToneMapping,
+    #[serde(default)]
+    pub primaries: Primaries,
+    #[serde(default)]
+    pub dither: Dither,
+    #[serde(default)]
+    pub sampler: SamplerKind,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_samples() -> u32 {
+    1
+}
+
+fn default_shadow_bias() -> f64 {
+    1e-9
+}
+
+fn default_max_recursion_depth() -> u32 {
+    8
+}
+
+fn default_integrator() -> Integrator {
+    Integrator::Whitted
+}
+
+fn default_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_fov_axis() -> FovAxis {
+    FovAxis::Horizontal
+}
+
+fn default_focus_distance() -> f64 {
+    1.0
+}
+
+fn default_one() -> f64 {
+    1.0
+}
+
+fn default_light_samples() -> u32 {
+    1
+}
+
+fn default_color_space() -> ColorSpace {
+    ColorSpace::Srgb
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub eye: [f64; 3],
+    pub target: [f64; 3],
+    #[serde(default = "default_up")]
+    pub up: [f64; 3],
+    pub fov: f64,
+    #[serde(default = "default_fov_axis")]
+    pub fov_axis: FovAxis,
+    #[serde(default)]
+    pub aperture: f64,
+    #[serde(default = "default_focus_distance")]
+    pub focus_distance: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDescription {
+    pub color: ColorationDescription,
+    #[serde(default = "default_surface")]
+    pub surface: SurfaceDescription,
+    pub albedo: f64,
+    #[serde(default)]
+    pub emission: [f64; 3],
+    #[serde(default)]
+    pub emission_strength: f64,
+}
+
+fn default_surface() -> SurfaceDescription {
+    SurfaceDescription::Diffuse
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SurfaceDescription {
+    Diffuse,
+    Reflective { reflectivity: f64 },
+    Refractive { index: f64, transparency: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ColorationDescription {
+    Color {
+        color: [f64; 3],
+    },
+    Texture {
+        path: PathBuf,
+        #[serde(default = "default_color_space")]
+        color_space: ColorSpace,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDescription {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: MaterialDescription,
+    },
+    Plane {
+        origin: [f64; 3],
+        normal: [f64; 3],
+        material: MaterialDescription,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        #[serde(default)]
+        normals: Option<[[f64; 3]; 3]>,
+        #[serde(default)]
+        tex_coords: Option<[[f64; 2]; 3]>,
+        material: MaterialDescription,
+    },
+    Mesh {
+        positions: Vec<[f64; 3]>,
+        #[serde(default)]
+        normals: Vec<[f64; 3]>,
+        #[serde(default)]
+        tex_coords: Vec<[f64; 2]>,
+        indices: Vec<[usize; 3]>,
+        material: MaterialDescription,
+    },
+    // every mesh in a wavefront obj file, with materials from its mtl files
+    Obj {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LightDescription {
+    Spherical {
+        position: [f64; 3],
+        color: [f64; 3],
+        intensity: f64,
+        #[serde(default)]
+        radius: f64,
+        #[serde(default = "default_light_samples")]
+        samples: u32,
+    },
+    Directional {
+        direction: [f64; 3],
+        color: [f64; 3],
+        intensity: f64,
+    },
+    Rectangular {
+        position: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        color: [f64; 3],
+        intensity: f64,
+        #[serde(default = "default_light_samples")]
+        samples: u32,
+    },
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        color: [f64; 3],
+        intensity: f64,
+        inner_angle: f64,
+        outer_angle: f64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EnvironmentDescription {
+    Constant {
+        color: [f64; 3],
+    },
+    Gradient {
+        zenith: [f64; 3],
+        horizon: [f64; 3],
+        ground: [f64; 3],
+    },
+    Image {
+        path: PathBuf,
+        #[serde(default = "default_one")]
+        intensity: f64,
+    },
+    PreethamSky {
+        sun_direction: [f64; 3],
+        turbidity: f64,
+        #[serde(default = "default_one")]
+        intensity: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneFormat {
+    Json,
+    Ron,
+    Toml,
+}
+
+impl SceneFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<SceneFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(SceneFormat::Json),
+            "ron" => Some(SceneFormat::Ron),
+            "toml" => Some(SceneFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    UnknownFormat(PathBuf),
+    // field is the path to the offending value, such as objects[2].material.albedo
+    Parse {
+        field: String,
+        message: String,
+    },
+    Serialize(String),
+    // a value that parsed but can't be rendered, with field as for Parse
+    Invalid {
+        field: String,
+        message: String,
+    },
+    Texture {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Obj(ObjError),
+    // a part of the scene that was built in code and has no file representation
+    NotDescribable(String),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io { path, source } => {
+                write!(f, "failed to access {}: {}", path.display(), source)
+            }
+            SceneFileError::UnknownFormat(path) => write!(
+                f,
+                "unknown scene format for {}, expected .json, .ron or .toml",
+                path.display()
+            ),
+            SceneFileError::Parse { field, message }
+            | SceneFileError::Invalid { field, message } => write!(f, "{}: {}", field, message),
+            SceneFileError::Serialize(message) => write!(f, "failed to write scene: {}", message),
+            SceneFileError::Texture { path, source } => {
+                write!(f, "failed to load image {}: {}", path.display(), source)
+            }
+            SceneFileError::Obj(e) => e.fmt(f),
+            SceneFileError::NotDescribable(what) => {
+                write!(f, "{} cannot be written to a scene file", what)
+            }
+        }
+    }
+}
+
+impl Error for SceneFileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneFileError::Io { source, .. } => Some(source),
+            SceneFileError::Texture { source, .. } => Some(source),
+            SceneFileError::Obj(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ObjError> for SceneFileError {
+    fn from(e: ObjError) -> SceneFileError {
+        SceneFileError::Obj(e)
+    }
+}
+
+fn parse_error<E: fmt::Display>(error: serde_path_to_error::Error<E>) -> SceneFileError {
+    SceneFileError::Parse {
+        field: error.path().to_string(),
+        message: error.inner().to_string(),
+    }
+}
+
+fn invalid(field: &str, message: &str) -> SceneFileError {
+    SceneFileError::Invalid {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn validate_tone_mapping(tone_mapping: &ToneMapping) -> Result<(), SceneFileError> {
+    // both operators divide by a function of the white point that is zero at zero
+    let (field, white) = match tone_mapping.operator {
+        ToneMapOperator::ExtendedReinhard { white } => {
+            ("tone_mapping.operator.extended_reinhard.white", white)
+        }
+        ToneMapOperator::Uncharted2 { white } => ("tone_mapping.operator.uncharted2.white", white),
+        _ => return Ok(()),
+    };
+    if !(white > 0.0 && white.is_finite()) {
+        return Err(invalid(field, "white point must be a positive number"));
+    }
+    Ok(())
+}
+
+fn validate_object(field: &str, object: &ObjectDescription) -> Result<(), SceneFileError> {
+    if let ObjectDescription::Mesh {
+        positions,
+        normals,
+        tex_coords,
+        indices,
+        ..
+    } = object
+    {
+        // vertex attributes are optional, but every vertex needs one if any does
+        for (name, length) in [("normals", normals.len()), ("tex_coords", tex_coords.len())] {
+            if length != 0 && length != positions.len() {
+                return Err(invalid(
+                    &format!("{}.{}", field, name),
+                    "must have one entry per position, or none",
+                ));
+            }
+        }
+        if indices.iter().flatten().any(|&i| i >= positions.len()) {
+            return Err(invalid(
+                &format!("{}.indices", field),
+                "must refer to one of the positions",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_light(field: &str, light: &LightDescription) -> Result<(), SceneFileError> {
+    match light {
+        LightDescription::Rectangular { u, v, .. }
+            if na::Vector3::from(*u).cross(&na::Vector3::from(*v)) == na::Vector3::zeros() =>
+        {
+            Err(invalid(field, "u and v must span an area"))
+        }
+        LightDescription::Spot {
+            inner_angle,
+            outer_angle,
+            ..
+        } if inner_angle > outer_angle => Err(invalid(
+            &format!("{}.inner_angle", field),
+            "must not be larger than outer_angle",
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn validate_filter(filter: &Filter) -> Result<(), SceneFileError> {
+    let positive = |field, value: f64| {
+        if value > 0.0 && value.is_finite() {
+            Ok(())
+        } else {
+            Err(invalid(field, "must be a positive number"))
+        }
+    };
+    positive("filter.radius", filter.radius)?;
+    match filter.shape {
+        // a gaussian that doesn't fall off and a lanczos window of no lobes weigh every sample
+        // as zero or infinity
+        FilterShape::Gaussian { alpha } => positive("filter.shape.gaussian.alpha", alpha),
+        FilterShape::Lanczos { tau } => positive("filter.shape.lanczos.tau", tau),
+        FilterShape::MitchellNetravali { b, c } => {
+            for (field, value) in [
+                ("filter.shape.mitchell_netravali.b", b),
+                ("filter.shape.mitchell_netravali.c", c),
+            ] {
+                if !value.is_finite() {
+                    return Err(invalid(field, "must be a finite number"));
+                }
+            }
+            Ok(())
+        }
+        FilterShape::Box | FilterShape::Tent => Ok(()),
+    }
+}
+
+fn vector<T>(v: [f64; 3]) -> na::Vector3<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    na::Vector3::from(v).map(na::convert)
+}
+
+fn point<T>(p: [f64; 3]) -> na::Point3<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    vector(p).into()
+}
+
+fn vector2<T>(v: [f64; 2]) -> na::Vector2<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    na::Vector2::from(v).map(na::convert)
+}
+
+fn scalar<T>(value: T) -> f64
+where
+    T: na::RealField + ToPrimitive,
+{
+    value.to_f64().unwrap()
+}
+
+fn describe_vector<T>(v: &na::Vector3<T>) -> [f64; 3]
+where
+    T: na::RealField + ToPrimitive,
+{
+    v.map(scalar).into()
+}
+
+fn describe_point<T>(p: &na::Point3<T>) -> [f64; 3]
+where
+    T: na::RealField + ToPrimitive,
+{
+    describe_vector(&p.coords)
+}
+
+fn describe_vector2<T>(v: &na::Vector2<T>) -> [f64; 2]
+where
+    T: na::RealField + ToPrimitive,
+{
+    v.map(scalar).into()
+}
+
+impl SceneDescription {
+    pub fn parse(source: &str, format: SceneFormat) -> Result<SceneDescription, SceneFileError> {
+        match format {
+            SceneFormat::Json => {
+                let mut deserializer = serde_json::Deserializer::from_str(source);
+                serde_path_to_error::deserialize(&mut deserializer).map_err(parse_error)
+            }
+            SceneFormat::Ron => {
+                let mut deserializer =
+                    ron::Deserializer::from_str(source).map_err(|e| SceneFileError::Parse {
+                        field: ".".to_string(),
+                        message: e.to_string(),
+                    })?;
+                serde_path_to_error::deserialize(&mut deserializer).map_err(parse_error)
+            }
+            SceneFormat::Toml => {
+                let deserializer = toml::Deserializer::new(source);
+                serde_path_to_error::deserialize(deserializer).map_err(parse_error)
+            }
+        }
+    }
+
+    pub fn to_string(&self, format: SceneFormat) -> Result<String, SceneFileError> {
+        match format {
+            SceneFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            SceneFormat::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(|e| e.to_string()),
+            SceneFormat::Toml => toml::to_string_pretty(self).map_err(|e| e.to_string()),
+        }
+        .map_err(SceneFileError::Serialize)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SceneDescription, SceneFileError> {
+        let path = path.as_ref();
+        let format = SceneFormat::from_path(path)
+            .ok_or_else(|| SceneFileError::UnknownFormat(path.into()))?;
+        let source = fs::read_to_string(path).map_err(|source| SceneFileError::Io {
+            path: path.into(),
+            source,
+        })?;
+        SceneDescription::parse(&source, format)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SceneFileError> {
+        let path = path.as_ref();
+        let format = SceneFormat::from_path(path)
+            .ok_or_else(|| SceneFileError::UnknownFormat(path.into()))?;
+        fs::write(path, self.to_string(format)?).map_err(|source| SceneFileError::Io {
+            path: path.into(),
+            source,
+        })
+    }
+
+    // checks for values the formats accept but a render can't use
+    pub fn validate(&self) -> Result<(), SceneFileError> {
+        for (field, value) in [
+            ("width", self.width),
+            ("height", self.height),
+            ("samples", self.samples),
+        ] {
+            if value == 0 {
+                return Err(invalid(field, "must be at least 1"));
+            }
+        }
+        if self.camera.eye == self.camera.target {
+            return Err(invalid(
+                "camera.target",
+                "must be somewhere else than the eye",
+            ));
+        }
+        for (i, object) in self.objects.iter().enumerate() {
+            validate_object(&format!("objects[{}]", i), object)?;
+        }
+        for (i, light) in self.lights.iter().enumerate() {
+            validate_light(&format!("lights[{}]", i), light)?;
+        }
+        validate_tone_mapping(&self.tone_mapping)?;
+        validate_filter(&self.filter)
+    }
+
+    // loads the files the description refers to, relative to base_dir
+    pub fn build<T>(&self, base_dir: &Path) -> Result<LoadedScene<T>, SceneFileError>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        self.validate()?;
+        let mut builder = Builder {
+            base_dir,
+            textures: HashMap::new(),
+            warnings: Vec::new(),
+        };
+
+        let mut objects = Vec::new();
+        for object in &self.objects {
+            builder.add_object(object, &mut objects)?;
+        }
+
+        let camera = &self.camera;
+        let mut scene = Scene::new(
+            self.width,
+            self.height,
+            Camera {
+                eye: point(camera.eye),
+                target: point(camera.target),
+                up: vector(camera.up),
+                fov: na::convert(camera.fov),
+                fov_axis: camera.fov_axis,
+                aperture: na::convert(camera.aperture),
+                focus_distance: na::convert(camera.focus_distance),
+            },
+        );
+        scene.samples = self.samples;
+        scene.objects = objects;
+        scene.lights = self.lights.iter().map(build_light).collect();
+        scene.shadow_bias = na::convert(self.shadow_bias);
+        scene.max_recursion_depth = self.max_recursion_depth;
+        scene.integrator = self.integrator;
+        scene.environment = self
+            .environment
+            .as_ref()
+            .map(|e| builder.environment(e))
+            .transpose()?;
+        scene.tone_mapping = self.tone_mapping;
+        scene.primaries = self.primaries;
+        scene.dither = self.dither;
+        scene.sampler = self.sampler;
+        scene.filter = self.filter;
+        scene.seed = self.seed;
+        Ok(LoadedScene {
+            scene,
+            warnings: builder.warnings,
+        })
+    }
+
+    // the inverse of build, with the paths of loaded files made relative to base_dir
+    pub fn from_scene<T>(
+        scene: &Scene<T>,
+        base_dir: &Path,
+    ) -> Result<SceneDescription, SceneFileError>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let describer = Describer {
+            base_dir: normalize(&absolute(base_dir)),
+        };
+        let camera = &scene.camera;
+        Ok(SceneDescription {
+            width: scene.width,
+            height: scene.height,
+            samples: scene.samples,
+            camera: CameraDescription {
+                eye: describe_point(&camera.eye),
+                target: describe_point(&camera.target),
+                up: describe_vector(&camera.up),
+                fov: scalar(camera.fov),
+                fov_axis: camera.fov_axis,
+                aperture: scalar(camera.aperture),
+                focus_distance: scalar(camera.focus_distance),
+            },
+            objects: describer.objects(&scene.objects)?,
+            lights: scene
+                .lights
+                .iter()
+                .map(|l| {
+                    describe_light(l.as_ref())
+                        .ok_or_else(|| SceneFileError::NotDescribable(format!("{:?}", l)))
+                })
+                .collect::<Result<_, _>>()?,
+            shadow_bias: scalar(scene.shadow_bias),
+            max_recursion_depth: scene.max_recursion_depth,
+            integrator: scene.integrator,
+            environment: scene
+                .environment
+                .as_ref()
+                .map(|e| {
+                    describer
+                        .environment(e.as_ref())
+                        .ok_or_else(|| SceneFileError::NotDescribable("the environment".into()))
+                })
+                .transpose()?,
+            tone_mapping: scene.tone_mapping,
+            primaries: scene.primaries,
+            dither: scene.dither,
+            sampler: scene.sampler,
+            filter: scene.filter,
+            seed: scene.seed,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct LoadedScene<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    pub scene: Scene<T>,
+    // problems with the files the scene refers to that didn't stop it loading
+    pub warnings: Vec<String>,
+}
+
+pub fn load_scene<T, P>(path: P) -> Result<LoadedScene<T>, SceneFileError>
+where
+    T: na::RealField + ToPrimitive,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    SceneDescription::open(path)?.build(base_dir)
+}
+
+pub fn save_scene<T, P>(scene: &Scene<T>, path: P) -> Result<(), SceneFileError>
+where
+    T: na::RealField + ToPrimitive,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    SceneDescription::from_scene(scene, base_dir)?.save(path)
+}
+
+struct Builder<'a> {
+    base_dir: &'a Path,
+    textures: HashMap<PathBuf, DynamicImage>,
+    warnings: Vec<String>,
+}
+
+impl Builder<'_> {
+    fn texture(&mut self, path: &Path) -> Result<DynamicImage, SceneFileError> {
+        let path = self.base_dir.join(path);
+        if let Some(texture) = self.textures.get(&path) {
+            return Ok(texture.clone());
+        }
+        let texture = image::open(&path).map_err(|source| SceneFileError::Texture {
+            path: path.clone(),
+            source,
+        })?;
+        self.textures.insert(path, texture.clone());
+        Ok(texture)
+    }
+
+    fn material<T>(
+        &mut self,
+        description: &MaterialDescription,
+    ) -> Result<Material<T>, SceneFileError>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let color: Box<dyn Coloration<T>> = match &description.color {
+            ColorationDescription::Color { color } => Box::new(Color {
+                color: vector(*color),
+            }),
+            ColorationDescription::Texture { path, color_space } => Box::new(Texture {
+                texture: self.texture(path)?,
+                color_space: *color_space,
+                path: Some(absolute(&self.base_dir.join(path))),
+            }),
+        };
+        let surface = match description.surface {
+            SurfaceDescription::Diffuse => SurfaceType::Diffuse,
+            SurfaceDescription::Reflective { reflectivity } => SurfaceType::Reflective {
+                reflectivity: na::convert(reflectivity),
+            },
+            SurfaceDescription::Refractive {
+                index,
+                transparency,
+            } => SurfaceType::Refractive {
+                index: na::convert(index),
+                transparency: na::convert(transparency),
+            },
+        };
+
+        Ok(Material {
+            color,
+            surface,
+            albedo: na::convert(description.albedo),
+            emission: vector(description.emission),
+            emission_strength: na::convert(description.emission_strength),
+        })
+    }
+
+    fn add_object<T>(
+        &mut self,
+        description: &ObjectDescription,
+        objects: &mut Vec<Box<dyn Intersectable<T>>>,
+    ) -> Result<(), SceneFileError>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let object: Box<dyn Intersectable<T>> = match description {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere {
+                center: point(*center),
+                radius: na::convert(*radius),
+                material: self.material(material)?,
+            }),
+            ObjectDescription::Plane {
+                origin,
+                normal,
+                material,
+            } => Box::new(Plane {
+                origin: point(*origin),
+                normal: vector(*normal),
+                material: self.material(material)?,
+            }),
+            ObjectDescription::Triangle {
+                vertices,
+                normals,
+                tex_coords,
+                material,
+            } => Box::new(Triangle {
+                vertices: vertices.map(point),
+                normals: normals.map(|n| n.map(vector)),
+                tex_coords: tex_coords.map(|t| t.map(vector2)),
+                material: self.material(material)?,
+            }),
+            ObjectDescription::Mesh {
+                positions,
+                normals,
+                tex_coords,
+                indices,
+                material,
+            } => Box::new(TriangleMesh::new(
+                positions.iter().copied().map(point).collect(),
+                normals.iter().copied().map(vector).collect(),
+                tex_coords.iter().copied().map(vector2).collect(),
+                indices.clone(),
+                self.material(material)?,
+            )),
+            ObjectDescription::Obj { path } => {
+                let loaded = load_obj(self.base_dir.join(path))?;
+                objects.extend(loaded.objects);
+                self.warnings.extend(loaded.warnings);
+                return Ok(());
+            }
+        };
+        objects.push(object);
+        Ok(())
+    }
+
+    fn environment<T>(
+        &mut self,
+        description: &EnvironmentDescription,
+    ) -> Result<Box<dyn Environment<T>>, SceneFileError>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        Ok(match description {
+            EnvironmentDescription::Constant { color } => Box::new(ConstantEnvironment {
+                color: vector(*color),
+            }),
+            EnvironmentDescription::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => Box::new(GradientEnvironment {
+                zenith: vector(*zenith),
+                horizon: vector(*horizon),
+                ground: vector(*ground),
+            }),
+            EnvironmentDescription::Image { path, intensity } => {
+                let full_path = self.base_dir.join(path);
+                let mut environment = ImageEnvironment::open(&full_path, na::convert(*intensity))
+                    .map_err(|source| SceneFileError::Texture {
+                    path: full_path.clone(),
+                    source,
+                })?;
+                environment.path = Some(absolute(&full_path));
+                Box::new(environment)
+            }
+            EnvironmentDescription::PreethamSky {
+                sun_direction,
+                turbidity,
+                intensity,
+            } => Box::new(PreethamSky {
+                sun_direction: vector(*sun_direction),
+                turbidity: na::convert(*turbidity),
+                intensity: na::convert(*intensity),
+            }),
+        })
+    }
+}
+
+fn build_light<T>(description: &LightDescription) -> Box<dyn Light<T>>
+where
+    T: na::RealField + ToPrimitive,
+{
+    match *description {
+        LightDescription::Spherical {
+            position,
+            color,
+            intensity,
+            radius,
+            samples,
+        } => Box::new(SphericalLight {
+            position: point(position),
+            color: vector(color),
+            intensity: na::convert(intensity),
+            radius: na::convert(radius),
+            samples,
+        }),
+        LightDescription::Directional {
+            direction,
+            color,
+            intensity,
+        } => Box::new(DirectionalLight {
+            direction: vector(direction),
+            color: vector(color),
+            intensity: na::convert(intensity),
+        }),
+        LightDescription::Rectangular {
+            position,
+            u,
+            v,
+            color,
+            intensity,
+            samples,
+        } => Box::new(RectangularLight {
+            position: point(position),
+            u: vector(u),
+            v: vector(v),
+            color: vector(color),
+            intensity: na::convert(intensity),
+            samples,
+        }),
+        LightDescription::Spot {
+            position,
+            direction,
+            color,
+            intensity,
+            inner_angle,
+            outer_angle,
+        } => Box::new(SpotLight {
+            position: point(position),
+            direction: vector(direction),
+            color: vector(color),
+            intensity: na::convert(intensity),
+            inner_angle: na::convert(inner_angle),
+            outer_angle: na::convert(outer_angle),
+        }),
+    }
+}
+
+// removes . and resolves .. without touching the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// path relative to base_dir, which is absolute and normalized. paths that only share the root
+// with it stay absolute
+fn relative_path(path: &Path, base_dir: &Path) -> PathBuf {
+    let path = normalize(&absolute(path));
+    let shared = path
+        .components()
+        .zip(base_dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if shared <= 1 {
+        return path;
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in shared..base_dir.components().count() {
+        relative.push("..");
+    }
+    relative.extend(path.components().skip(shared));
+    relative
+}
+
+// writes built parts of a scene back out as descriptions
+struct Describer {
+    base_dir: PathBuf,
+}
+
+impl Describer {
+    // meshes loaded from the same obj file one after another go back to a single reference
+    // to it
+    fn objects<T>(
+        &self,
+        objects: &[Box<dyn Intersectable<T>>],
+    ) -> Result<Vec<ObjectDescription>, SceneFileError>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let mut descriptions = Vec::with_capacity(objects.len());
+        let mut last_source = None;
+        for object in objects {
+            let any: &dyn Any = object.as_ref();
+            let source = any
+                .downcast_ref::<TriangleMesh<T>>()
+                .and_then(|mesh| mesh.source());
+            if let Some(source) = source {
+                if last_source != Some(source) {
+                    descriptions.push(ObjectDescription::Obj {
+                        path: relative_path(source, &self.base_dir),
+                    });
+                }
+                last_source = Some(source);
+                continue;
+            }
+
+            last_source = None;
+            let description = self
+                .object(object.as_ref())
+                .ok_or_else(|| SceneFileError::NotDescribable(format!("{:?}", object)))?;
+            descriptions.push(description);
+        }
+        Ok(descriptions)
+    }
+
+    fn object<T>(&self, object: &dyn Intersectable<T>) -> Option<ObjectDescription>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let any: &dyn Any = object;
+        if let Some(sphere) = any.downcast_ref::<Sphere<T>>() {
+            return Some(ObjectDescription::Sphere {
+                center: describe_point(&sphere.center),
+                radius: scalar(sphere.radius),
+                material: self.material(&sphere.material)?,
+            });
+        }
+        if let Some(plane) = any.downcast_ref::<Plane<T>>() {
+            return Some(ObjectDescription::Plane {
+                origin: describe_point(&plane.origin),
+                normal: describe_vector(&plane.normal),
+                material: self.material(&plane.material)?,
+            });
+        }
+        if let Some(triangle) = any.downcast_ref::<Triangle<T>>() {
+            return Some(ObjectDescription::Triangle {
+                vertices: triangle.vertices.each_ref().map(describe_point),
+                normals: triangle.normals.map(|n| n.each_ref().map(describe_vector)),
+                tex_coords: triangle
+                    .tex_coords
+                    .map(|t| t.each_ref().map(describe_vector2)),
+                material: self.material(&triangle.material)?,
+            });
+        }
+        if let Some(mesh) = any.downcast_ref::<TriangleMesh<T>>() {
+            return Some(ObjectDescription::Mesh {
+                positions: mesh.positions().iter().map(describe_point).collect(),
+                normals: mesh.normals().iter().map(describe_vector).collect(),
+                tex_coords: mesh.tex_coords().iter().map(describe_vector2).collect(),
+                indices: mesh.indices().to_vec(),
+                material: self.material(&mesh.material)?,
+            });
+        }
+        None
+    }
+
+    fn material<T>(&self, material: &Material<T>) -> Option<MaterialDescription>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let surface = match material.surface {
+            SurfaceType::Diffuse => SurfaceDescription::Diffuse,
+            SurfaceType::Reflective { reflectivity } => SurfaceDescription::Reflective {
+                reflectivity: scalar(reflectivity),
+            },
+            SurfaceType::Refractive {
+                index,
+                transparency,
+            } => SurfaceDescription::Refractive {
+                index: scalar(index),
+                transparency: scalar(transparency),
+            },
+        };
+        Some(MaterialDescription {
+            color: self.coloration(material.color.as_ref())?,
+            surface,
+            albedo: scalar(material.albedo),
+            emission: describe_vector(&material.emission),
+            emission_strength: scalar(material.emission_strength),
+        })
+    }
+
+    fn coloration<T>(&self, coloration: &dyn Coloration<T>) -> Option<ColorationDescription>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let any: &dyn Any = coloration;
+        if let Some(color) = any.downcast_ref::<Color<T>>() {
+            return Some(ColorationDescription::Color {
+                color: describe_vector(&color.color),
+            });
+        }
+        let texture = any.downcast_ref::<Texture>()?;
+        Some(ColorationDescription::Texture {
+            path: relative_path(texture.path.as_ref()?, &self.base_dir),
+            color_space: texture.color_space,
+        })
+    }
+
+    fn environment<T>(&self, environment: &dyn Environment<T>) -> Option<EnvironmentDescription>
+    where
+        T: na::RealField + ToPrimitive,
+    {
+        let any: &dyn Any = environment;
+        if let Some(constant) = any.downcast_ref::<ConstantEnvironment<T>>() {
+            return Some(EnvironmentDescription::Constant {
+                color: describe_vector(&constant.color),
+            });
+        }
+        if let Some(gradient) = any.downcast_ref::<GradientEnvironment<T>>() {
+            return Some(EnvironmentDescription::Gradient {
+                zenith: describe_vector(&gradient.zenith),
+                horizon: describe_vector(&gradient.horizon),
+                ground: describe_vector(&gradient.ground),
+            });
+        }
+        if let Some(image) = any.downcast_ref::<ImageEnvironment<T>>() {
+            return Some(EnvironmentDescription::Image {
+                path: relative_path(image.path.as_ref()?, &self.base_dir),
+                intensity: scalar(image.intensity),
+            });
+        }
+        let sky = any.downcast_ref::<PreethamSky<T>>()?;
+        Some(EnvironmentDescription::PreethamSky {
+            sun_direction: describe_vector(&sky.sun_direction),
+            turbidity: scalar(sky.turbidity),
+            intensity: scalar(sky.intensity),
+        })
+    }
+}
+
+fn describe_light<T>(light: &dyn Light<T>) -> Option<LightDescription>
+where
+    T: na::RealField + ToPrimitive,
+{
+    let any: &dyn Any = light;
+    if let Some(light) = any.downcast_ref::<SphericalLight<T>>() {
+        return Some(LightDescription::Spherical {
+            position: describe_point(&light.position),
+            color: describe_vector(&light.color),
+            intensity: scalar(light.intensity),
+            radius: scalar(light.radius),
+            samples: light.samples,
+        });
+    }
+    if let Some(light) = any.downcast_ref::<DirectionalLight<T>>() {
+        return Some(LightDescription::Directional {
+            direction: describe_vector(&light.direction),
+            color: describe_vector(&light.color),
+            intensity: scalar(light.intensity),
+        });
+    }
+    if let Some(light) = any.downcast_ref::<RectangularLight<T>>() {
+        return Some(LightDescription::Rectangular {
+            position: describe_point(&light.position),
+            u: describe_vector(&light.u),
+            v: describe_vector(&light.v),
+            color: describe_vector(&light.color),
+            intensity: scalar(light.intensity),
+            samples: light.samples,
+        });
+    }
+    let light = any.downcast_ref::<SpotLight<T>>()?;
+    Some(LightDescription::Spot {
+        position: describe_point(&light.position),
+        direction: describe_vector(&light.direction),
+        color: describe_vector(&light.color),
+        intensity: scalar(light.intensity),
+        inner_angle: scalar(light.inner_angle),
+        outer_angle: scalar(light.outer_angle),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const JSON: &str = r#"{
+        "width": 64,
+        "height": 48,
+        "samples": 4,
+        "camera": { "eye": [0, 1, 5], "target": [0, 1, 0], "fov": 60, "fov_axis": "vertical" },
+        "objects": [
+            {
+                "type": "sphere",
+                "center": [0, 1, 0],
+                "radius": 1,
+                "material": {
+                    "color": { "type": "color", "color": [1, 0.5, 0.25] },
+                    "surface": { "type": "reflective", "reflectivity": 0.5 },
+                    "albedo": 0.18
+                }
+            },
+            {
+                "type": "plane",
+                "origin": [0, 0, 0],
+                "normal": [0, -1, 0],
+                "material": { "color": { "type": "color", "color": [1, 1, 1] }, "albedo": 0.5 }
+            }
+        ],
+        "lights": [
+            { "type": "directional", "direction": [0, -1, 0], "color": [1, 1, 1], "intensity": 5 },
+            {
+                "type": "spherical",
+                "position": [2, 4, 2],
+                "color": [1, 1, 1],
+                "intensity": 1000,
+                "radius": 0.5,
+                "samples": 4
+            }
+        ],
+        "integrator": { "path_tracing": { "russian_roulette_depth": 3 } },
+        "environment": { "type": "constant", "color": [0.1, 0.1, 0.2] },
+        "tone_mapping": { "exposure": 1, "operator": "aces_filmic" }
+    }"#;
+
+    #[test]
+    fn scenes_round_trip_through_every_format() {
+        let description = SceneDescription::parse(JSON, SceneFormat::Json).unwrap();
+        let scene = description.build::<f64>(Path::new("")).unwrap().scene;
+        assert_eq!(scene.objects.len(), 2);
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.camera.fov_axis, FovAxis::Vertical);
+        assert_eq!(scene.shadow_bias, 1e-9);
+
+        let exported = SceneDescription::from_scene(&scene, Path::new("")).unwrap();
+        assert_eq!(exported, description);
+
+        for &format in &[SceneFormat::Json, SceneFormat::Ron, SceneFormat::Toml] {
+            let text = exported.to_string(format).unwrap();
+            let parsed = SceneDescription::parse(&text, format)
+                .unwrap_or_else(|e| panic!("{:?} failed with {}:\n{}", format, e, text));
+            assert_eq!(parsed, description, "{:?} changed the scene", format);
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_field() {
+        let broken = JSON.replace("\"fov\": 60", "\"fov\": \"wide\"");
+        let error = SceneDescription::parse(&broken, SceneFormat::Json).unwrap_err();
+        assert!(
+            error.to_string().starts_with("camera.fov: "),
+            "unexpected error: {}",
+            error
+        );
+
+        let broken = JSON.replace("\"albedo\": 0.18", "\"albedo\": 0.18, \"shininess\": 2");
+        let error = SceneDescription::parse(&broken, SceneFormat::Json).unwrap_err();
+        assert!(
+            error.to_string().starts_with("objects[0]") && error.to_string().contains("shininess"),
+            "unexpected error: {}",
+            error
+        );
+
+        let toml = "width = 1\nheight = 1\n[camera]\neye = [0, 0, 0]\ntarget = [0, 0, -1]\n";
+        let error = SceneDescription::parse(toml, SceneFormat::Toml).unwrap_err();
+        assert!(
+            error.to_string().contains("fov"),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn unusable_values_are_rejected_when_building() {
+        let invalid = |from: &str, to: &str, field: &str| {
+            let broken = JSON.replace(from, to);
+            let description = SceneDescription::parse(&broken, SceneFormat::Json).unwrap();
+            let error = description.build::<f64>(Path::new("")).unwrap_err();
+            assert!(
+                error.to_string().starts_with(&format!("{}: ", field)),
+                "unexpected error: {}",
+                error
+            );
+        };
+
+        invalid("\"width\": 64", "\"width\": 0", "width");
+        invalid("\"height\": 48", "\"height\": 0", "height");
+        invalid("\"samples\": 4", "\"samples\": 0", "samples");
+        invalid(
+            r#""target": [0, 1, 0]"#,
+            r#""target": [0, 1, 5]"#,
+            "camera.target",
+        );
+
+        // a mesh is put in front of the other objects, and a light in front of the other lights
+        let mesh = |attributes: &str| {
+            format!(
+                r#""objects": [{{ "type": "mesh", "positions": [[0, 0, 0], [1, 0, 0], [0, 1, 0]],
+                    {}, "material": {{ "color": {{ "type": "color", "color": [1, 1, 1] }}, "albedo": 1 }} }},"#,
+                attributes
+            )
+        };
+        invalid(
+            r#""objects": ["#,
+            &mesh(r#""indices": [[0, 1, 5]]"#),
+            "objects[0].indices",
+        );
+        invalid(
+            r#""objects": ["#,
+            &mesh(r#""indices": [[0, 1, 2]], "normals": [[0, 0, 1]]"#),
+            "objects[0].normals",
+        );
+        invalid(
+            r#""objects": ["#,
+            &mesh(r#""indices": [[0, 1, 2]], "tex_coords": [[0, 0], [1, 0]]"#),
+            "objects[0].tex_coords",
+        );
+        invalid(
+            r#""lights": ["#,
+            r#""lights": [{ "type": "rectangular", "position": [0, 2, 0], "u": [1, 0, 0],
+                "v": [2, 0, 0], "color": [1, 1, 1], "intensity": 1 },"#,
+            "lights[0]",
+        );
+        invalid(
+            r#""lights": ["#,
+            r#""lights": [{ "type": "spot", "position": [0, 2, 0], "direction": [0, -1, 0],
+                "color": [1, 1, 1], "intensity": 1, "inner_angle": 40, "outer_angle": 30 },"#,
+            "lights[0].inner_angle",
+        );
+        // the json has no filter, so one is added in front of the tone mapping
+        let with_filter = |filter: &str| format!(r#""filter": {}, "tone_mapping": {{"#, filter);
+        for radius in ["0", "-0.5"] {
+            invalid(
+                r#""tone_mapping": {"#,
+                &with_filter(&format!(r#"{{ "radius": {} }}"#, radius)),
+                "filter.radius",
+            );
+        }
+        for alpha in ["0", "-2"] {
+            invalid(
+                r#""tone_mapping": {"#,
+                &with_filter(&format!(
+                    r#"{{ "shape": {{ "gaussian": {{ "alpha": {} }} }} }}"#,
+                    alpha
+                )),
+                "filter.shape.gaussian.alpha",
+            );
+        }
+        invalid(
+            r#""tone_mapping": {"#,
+            &with_filter(r#"{ "shape": { "lanczos": { "tau": 0 } } }"#),
+            "filter.shape.lanczos.tau",
+        );
+        for white in &["0", "-1"] {
+            invalid(
+                "\"aces_filmic\"",
+                &format!("{{ \"extended_reinhard\": {{ \"white\": {} }} }}", white),
+                "tone_mapping.operator.extended_reinhard.white",
+            );
+            invalid(
+                "\"aces_filmic\"",
+                &format!("{{ \"uncharted2\": {{ \"white\": {} }} }}", white),
+                "tone_mapping.operator.uncharted2.white",
+            );
+        }
+    }
+
+    #[test]
+    fn saved_scenes_refer_to_the_files_they_were_loaded_from() {
+        let dir = std::env::temp_dir().join("rustracer_scene_paths_test");
+        fs::create_dir_all(dir.join("assets")).unwrap();
+        fs::create_dir_all(dir.join("scenes")).unwrap();
+        image::RgbImage::new(2, 2)
+            .save(dir.join("assets/checker.png"))
+            .unwrap();
+        fs::write(
+            dir.join("assets/quads.mtl"),
+            "newmtl checker\nmap_Kd checker.png\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("assets/quads.obj"),
+            "mtllib quads.mtl\n\
+             v -1 -1 -3\nv 1 -1 -3\nv 1 1 -3\nv -1 1 -3\nvt 0 0\n\
+             o first\nusemtl checker\nf 1/1 2/1 3/1\n\
+             o second\nf 1/1 3/1 4/1\n",
+        )
+        .unwrap();
+        let textured = JSON.replace(
+            r#"{ "type": "color", "color": [1, 1, 1] }"#,
+            r#"{ "type": "texture", "path": "../assets/checker.png" }"#,
+        );
+        let with_obj = textured.replace(
+            r#""objects": ["#,
+            r#""objects": [{ "type": "obj", "path": "../assets/quads.obj" },"#,
+        );
+        fs::write(dir.join("scenes/scene.json"), with_obj).unwrap();
+
+        let scene = load_scene::<f64, _>(dir.join("scenes/scene.json"))
+            .unwrap()
+            .scene;
+        assert_eq!(scene.objects.len(), 4);
+        let copy = dir.join("exported/nested/scene.json");
+        fs::create_dir_all(copy.parent().unwrap()).unwrap();
+        save_scene(&scene, &copy).unwrap();
+
+        let saved = SceneDescription::open(&copy).unwrap();
+        assert_eq!(
+            saved.objects[0],
+            ObjectDescription::Obj {
+                path: "../../assets/quads.obj".into()
+            }
+        );
+        assert_eq!(saved.objects.len(), 3);
+        match &saved.objects[2] {
+            ObjectDescription::Plane { material, .. } => assert_eq!(
+                material.color,
+                ColorationDescription::Texture {
+                    path: "../../assets/checker.png".into(),
+                    color_space: ColorSpace::Srgb,
+                }
+            ),
+            other => panic!("expected the plane, got {:?}", other),
+        }
+        assert_eq!(load_scene::<f64, _>(&copy).unwrap().scene.objects.len(), 4);
+    }
+
+    #[test]
+    fn example_scenes_load() {
+        let loaded = load_scene::<f64, _>("scenes/spheres.toml").unwrap();
+        assert!(loaded.warnings.is_empty());
+        let scene = loaded.scene;
+        assert_eq!(scene.objects.len(), 4);
+        assert!(scene.environment.is_some());
+    }
+}
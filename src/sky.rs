@@ -0,0 +1,193 @@
+use nalgebra as na;
+use num::ToPrimitive;
+
+use crate::{environment::Environment, lights::DirectionalLight};
+
+// analytic daylight model from Preetham, Shirley and Smits, "A Practical Analytic Model for
+// Daylight". radiance comes out in kcd/m^2 before being scaled by intensity
+#[derive(Debug)]
+pub struct PreethamSky<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    // points from the ground towards the sun, +y is up
+    pub sun_direction: na::Vector3<T>,
+    // haziness of the atmosphere, 2 is very clear and 10 is hazy
+    pub turbidity: T,
+    pub intensity: T,
+}
+
+// coefficients for the Perez distribution in terms of turbidity, as (slope, intercept)
+const PEREZ_Y: [(f64, f64); 5] = [
+    (0.1787, -1.4630),
+    (-0.3554, 0.4275),
+    (-0.0227, 5.3251),
+    (0.1206, -2.5771),
+    (-0.0670, 0.3703),
+];
+const PEREZ_X: [(f64, f64); 5] = [
+    (-0.0193, -0.2592),
+    (-0.0665, 0.0008),
+    (-0.0004, 0.2125),
+    (-0.0641, -0.8989),
+    (-0.0033, 0.0452),
+];
+const PEREZ_CHROMA_Y: [(f64, f64); 5] = [
+    (-0.0167, -0.2608),
+    (-0.0950, 0.0092),
+    (-0.0079, 0.2102),
+    (-0.0441, -1.6537),
+    (-0.0109, 0.0529),
+];
+
+// zenith chromaticity polynomials, rows are turbidity^2, turbidity and 1
+const ZENITH_X: [[f64; 4]; 3] = [
+    [0.00166, -0.00375, 0.00209, 0.0],
+    [-0.02903, 0.06377, -0.03202, 0.00394],
+    [0.11693, -0.21196, 0.06052, 0.25886],
+];
+const ZENITH_Y: [[f64; 4]; 3] = [
+    [0.00275, -0.00610, 0.00317, 0.0],
+    [-0.04214, 0.08970, -0.04153, 0.00516],
+    [0.15346, -0.26756, 0.06670, 0.26688],
+];
+
+fn perez<T>(coefficients: &[T; 5], cos_theta: T, gamma: T) -> T
+where
+    T: na::RealField + ToPrimitive,
+{
+    let [a, b, c, d, e] = *coefficients;
+    let cos_gamma = gamma.cos();
+    (T::one() + a * (b / cos_theta).exp())
+        * (T::one() + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+fn zenith_chromaticity<T>(polynomial: &[[f64; 4]; 3], turbidity: T, theta_sun: T) -> T
+where
+    T: na::RealField + ToPrimitive,
+{
+    let thetas = [
+        theta_sun * theta_sun * theta_sun,
+        theta_sun * theta_sun,
+        theta_sun,
+        T::one(),
+    ];
+    let turbidities = [turbidity * turbidity, turbidity, T::one()];
+    let mut value = T::zero();
+    for (row, t) in polynomial.iter().zip(turbidities.iter()) {
+        for (&c, &theta) in row.iter().zip(thetas.iter()) {
+            value += *t * na::convert::<f64, T>(c) * theta;
+        }
+    }
+    value
+}
+
+impl<T> PreethamSky<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn coefficients(&self, table: &[(f64, f64); 5]) -> [T; 5] {
+        table.map(|(slope, intercept)| {
+            na::convert::<f64, T>(slope) * self.turbidity + na::convert::<f64, T>(intercept)
+        })
+    }
+
+    // a directional light standing in for the sun, tinted to match the sky around it
+    pub fn sun(&self, intensity: T) -> DirectionalLight<T> {
+        let color = self.radiance(&self.sun_direction);
+        let max = color.max();
+        let color = if max > T::zero() {
+            color / max
+        } else {
+            na::Vector3::repeat(T::one())
+        };
+
+        DirectionalLight {
+            direction: -self.sun_direction.normalize(),
+            color,
+            intensity,
+        }
+    }
+}
+
+impl<T> Environment<T> for PreethamSky<T>
+where
+    T: na::RealField + ToPrimitive,
+{
+    fn radiance(&self, direction: &na::Vector3<T>) -> na::Vector3<T> {
+        let sun = self.sun_direction.normalize();
+        // the model is only defined above the horizon, so the ground reflects the horizon
+        let mut direction = direction.normalize();
+        let horizon: T = na::convert(1e-3);
+        if direction.y < horizon {
+            direction.y = horizon;
+            direction = direction.normalize();
+        }
+
+        let theta_sun = sun.y.max(T::zero()).min(T::one()).acos();
+        let cos_theta = direction.y;
+        let gamma = direction.dot(&sun).max(-T::one()).min(T::one()).acos();
+
+        let turbidity = self.turbidity;
+        let chi = (na::convert::<f64, T>(4.0 / 9.0) - turbidity / na::convert(120.0))
+            * (T::pi() - theta_sun * na::convert(2.0));
+        let zenith_luminance = (na::convert::<f64, T>(4.0453) * turbidity - na::convert(4.9710))
+            * chi.tan()
+            - na::convert::<f64, T>(0.2155) * turbidity
+            + na::convert(2.4192);
+        let zenith_x = zenith_chromaticity(&ZENITH_X, turbidity, theta_sun);
+        let zenith_y = zenith_chromaticity(&ZENITH_Y, turbidity, theta_sun);
+
+        let distribute = |table, zenith: T| {
+            let coefficients = self.coefficients(table);
+            zenith * perez(&coefficients, cos_theta, gamma)
+                / perez(&coefficients, T::one(), theta_sun)
+        };
+        let luminance = distribute(&PEREZ_Y, zenith_luminance);
+        let x = distribute(&PEREZ_X, zenith_x);
+        let y = distribute(&PEREZ_CHROMA_Y, zenith_y);
+
+        // xyY to XYZ, then to linear rec. 709
+        let xyz = na::Vector3::new(
+            x / y * luminance,
+            luminance,
+            (T::one() - x - y) / y * luminance,
+        );
+        let to_rgb = na::Matrix3::new(
+            3.2404542, -1.5371385, -0.4985314, -0.9692660, 1.8760108, 0.0415560, 0.0556434,
+            -0.2040259, 1.0572252,
+        )
+        .map(|c: f64| na::convert::<f64, T>(c));
+        (to_rgb * xyz).map(|c| c.max(T::zero())) * self.intensity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+
+    use super::*;
+
+    #[test]
+    fn sky_is_blue_away_from_the_sun_and_brighter_near_it() {
+        let sky = PreethamSky::<f64> {
+            sun_direction: na::Vector3::new(0.0, 0.5, -1.0).normalize(),
+            turbidity: 3.0,
+            intensity: 1.0,
+        };
+
+        let away = sky.radiance(&na::Vector3::new(0.0, 0.5, 1.0));
+        assert!(
+            away.z > away.x,
+            "sky facing away from the sun should be blue, got {}",
+            away
+        );
+
+        let near_sun = sky.radiance(&na::Vector3::new(0.0, 0.52, -1.0));
+        assert!(near_sun.sum() > away.sum());
+
+        let sun = sky.sun(5.0);
+        assert!((sun.direction + sky.sun_direction).norm() < 1e-12);
+        assert!((sun.color.max() - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,119 @@
+// the order tiles are handed to the workers, which is the order the image fills in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileOrder {
+    // row by row from the top left
+    Scanline,
+    // outwards from the center, where the subject usually is
+    #[default]
+    Spiral,
+    // along a hilbert curve, so consecutive tiles are neighbors and share cached geometry
+    Hilbert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn pixels(self) -> impl Iterator<Item = (u32, u32)> {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| (x, y)))
+    }
+}
+
+// distance along a hilbert curve filling a size x size grid, where size is a power of two
+fn hilbert_index(size: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut s = size / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        index += u64::from(s) * u64::from(s) * u64::from((3 * rx) ^ ry);
+        // rotate the quadrant so the curve stays connected
+        if ry == 0 {
+            if rx == 1 {
+                x = size - 1 - x;
+                y = size - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+// splits the image into tiles of at most size x size pixels
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+
+    let mut grid = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect::<Vec<_>>();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let center = (f64::from(columns) / 2.0, f64::from(rows) / 2.0);
+            grid.sort_by(|&a, &b| {
+                let key = |(column, row): (u32, u32)| {
+                    let dx = f64::from(column) + 0.5 - center.0;
+                    let dy = f64::from(row) + 0.5 - center.1;
+                    // rings of tiles around the center, each walked around clockwise
+                    (dx.abs().max(dy.abs()).round(), dy.atan2(dx))
+                };
+                let (a, b) = (key(a), key(b));
+                a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+            });
+        }
+        TileOrder::Hilbert => {
+            let size = columns.max(rows).next_power_of_two();
+            grid.sort_by_key(|&(column, row)| hilbert_index(size, column, row));
+        }
+    }
+
+    grid.into_iter()
+        .map(|(column, row)| {
+            let (x, y) = (column * size, row * size);
+            Tile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        for &order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let (width, height) = (37, 23);
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in tiles(width, height, 8, order) {
+                for (x, y) in tile.pixels() {
+                    covered[(y * width + x) as usize] += 1;
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1), "{:?} missed pixels", order);
+        }
+
+        let hilbert = tiles(64, 64, 8, TileOrder::Hilbert);
+        for pair in hilbert.windows(2) {
+            let distance = (pair[0].x as i32 - pair[1].x as i32).abs()
+                + (pair[0].y as i32 - pair[1].y as i32).abs();
+            assert_eq!(distance, 8, "hilbert tiles should be neighbors");
+        }
+
+        let spiral = tiles(64, 64, 8, TileOrder::Spiral);
+        assert!(spiral[0].x >= 24 && spiral[0].x <= 32 && spiral[0].y >= 24 && spiral[0].y <= 32);
+    }
+}
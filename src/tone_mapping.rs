@@ -0,0 +1,115 @@
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+// maps linear radiance of any brightness into the [0, 1] range of the output image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapOperator {
+    // leaves values as they are, so anything brighter than 1 clips
+    Clamp,
+    Reinhard,
+    // reinhard that reaches 1 at the given white point instead of at infinity
+    ExtendedReinhard { white: f32 },
+    // narkowicz's fit of the academy color encoding system reference transform
+    AcesFilmic,
+    // hable's filmic curve, normalized so the given white point maps to 1
+    Uncharted2 { white: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToneMapping {
+    // in stops, so every step of 1 doubles the brightness
+    pub exposure: f32,
+    pub operator: ToneMapOperator,
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping {
+            exposure: 0.0,
+            operator: ToneMapOperator::Clamp,
+        }
+    }
+}
+
+fn uncharted2_partial(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+impl ToneMapping {
+    pub fn apply(&self, color: na::Vector3<f32>) -> na::Vector3<f32> {
+        let color = color * self.exposure.exp2();
+        color.map(|x| {
+            let x = x.max(0.0);
+            match self.operator {
+                ToneMapOperator::Clamp => x,
+                ToneMapOperator::Reinhard => x / (1.0 + x),
+                ToneMapOperator::ExtendedReinhard { white } => {
+                    x * (1.0 + x / (white * white)) / (1.0 + x)
+                }
+                ToneMapOperator::AcesFilmic => {
+                    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+                }
+                ToneMapOperator::Uncharted2 { white } => {
+                    const EXPOSURE_BIAS: f32 = 2.0;
+                    uncharted2_partial(x * EXPOSURE_BIAS) / uncharted2_partial(white)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+
+    use super::*;
+
+    #[test]
+    fn operators_compress_highlights_monotonically() {
+        let operators = [
+            ToneMapOperator::Reinhard,
+            ToneMapOperator::ExtendedReinhard { white: 1000.0 },
+            ToneMapOperator::AcesFilmic,
+            ToneMapOperator::Uncharted2 { white: 11.2 },
+        ];
+        for &operator in &operators {
+            let tone_mapping = ToneMapping {
+                exposure: 0.0,
+                operator,
+            };
+            let map = |x: f32| tone_mapping.apply(na::Vector3::repeat(x)).x;
+
+            assert!(map(0.0).abs() < 1e-3, "{:?} should keep black", operator);
+            let mut previous = map(0.0);
+            for i in 1..100 {
+                let mapped = map(i as f32 * 0.1);
+                assert!(mapped > previous, "{:?} is not increasing", operator);
+                previous = mapped;
+            }
+            assert!(map(1000.0) < 2.0, "{:?} does not compress", operator);
+        }
+
+        let extended = ToneMapping {
+            exposure: 0.0,
+            operator: ToneMapOperator::ExtendedReinhard { white: 4.0 },
+        };
+        assert!((extended.apply(na::Vector3::repeat(4.0)).x - 1.0).abs() < 1e-6);
+
+        let brighter = ToneMapping {
+            exposure: 1.0,
+            ..ToneMapping::default()
+        };
+        assert_eq!(
+            brighter.apply(na::Vector3::new(0.25, 0.5, 1.0)),
+            na::Vector3::new(0.5, 1.0, 2.0)
+        );
+    }
+}